@@ -18,3 +18,62 @@ CREATE TABLE Move (
   san_plus TEXT NOT NULL,
   board_hash BIGINT NOT NULL -- 8 bytes
 );
+
+ALTER TABLE Game ADD COLUMN IF NOT EXISTS result TEXT; -- 1-0, 0-1, 1/2-1/2 or *
+
+CREATE INDEX IF NOT EXISTS move_board_hash_idx ON Move (board_hash);
+
+ALTER TABLE Move ADD COLUMN IF NOT EXISTS material TEXT; -- like KRPvKR, white pieces first
+
+CREATE INDEX IF NOT EXISTS move_material_idx ON Move (material);
+
+ALTER TABLE Move ADD COLUMN IF NOT EXISTS pawn_hash BIGINT; -- pawns and side to move only
+
+CREATE INDEX IF NOT EXISTS move_pawn_hash_idx ON Move (pawn_hash);
+
+-- Bitboards of the position, one bit per square (a1 = least significant).
+-- Indexed through piece_placement below, and checked with bitwise masks for absent pieces.
+ALTER TABLE Move ADD COLUMN IF NOT EXISTS white_bb BIGINT;
+ALTER TABLE Move ADD COLUMN IF NOT EXISTS black_bb BIGINT;
+ALTER TABLE Move ADD COLUMN IF NOT EXISTS pawn_bb BIGINT;
+ALTER TABLE Move ADD COLUMN IF NOT EXISTS knight_bb BIGINT;
+ALTER TABLE Move ADD COLUMN IF NOT EXISTS bishop_bb BIGINT;
+ALTER TABLE Move ADD COLUMN IF NOT EXISTS rook_bb BIGINT;
+ALTER TABLE Move ADD COLUMN IF NOT EXISTS queen_bb BIGINT;
+ALTER TABLE Move ADD COLUMN IF NOT EXISTS king_bb BIGINT;
+
+-- Every piece of the position as (color * 6 + role) * 64 + square, white first and roles from pawn
+-- to king, so patterns placing pieces are looked up in a GIN index instead of scanning the bitboards.
+CREATE OR REPLACE FUNCTION piece_placement(white BIGINT, black BIGINT, pawns BIGINT, knights BIGINT,
+                                           bishops BIGINT, rooks BIGINT, queens BIGINT, kings BIGINT)
+RETURNS SMALLINT[] LANGUAGE SQL IMMUTABLE PARALLEL SAFE AS $$
+  SELECT COALESCE(array_agg(
+           ((CASE WHEN white & (1::BIGINT << square) <> 0 THEN 0 ELSE 6 END + role) * 64 + square)::SMALLINT
+           ORDER BY square), '{}')
+  FROM generate_series(0, 63) AS square
+  CROSS JOIN (VALUES (0, pawns), (1, knights), (2, bishops), (3, rooks), (4, queens), (5, kings)) AS roles(role, bitboard)
+  WHERE bitboard & (1::BIGINT << square) <> 0
+$$;
+
+CREATE INDEX IF NOT EXISTS move_piece_placement_idx ON Move
+  USING gin (piece_placement(white_bb, black_bb, pawn_bb, knight_bb, bishop_bb, rook_bb, queen_bb, king_bb));
+
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX IF NOT EXISTS player_name_trgm_idx ON Player USING gin (player_name gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS game_white_idx ON Game (white);
+CREATE INDEX IF NOT EXISTS game_black_idx ON Game (black);
+
+ALTER TABLE Game ADD COLUMN IF NOT EXISTS plies INTEGER;
+
+ALTER TABLE Game ADD COLUMN IF NOT EXISTS eco TEXT;
+ALTER TABLE Game ADD COLUMN IF NOT EXISTS opening TEXT;
+
+ALTER TABLE Game ADD COLUMN IF NOT EXISTS time_control TEXT; -- like 180+2, in seconds
+ALTER TABLE Move ADD COLUMN IF NOT EXISTS clock INTEGER; -- milliseconds left to the mover, from [%clk] comments
+
+ALTER TABLE Game ADD COLUMN IF NOT EXISTS site TEXT;
+ALTER TABLE Game ADD COLUMN IF NOT EXISTS termination TEXT; -- like Normal or Time forfeit
+
+-- the moves of a game, looked up for each related game, novelty and opening line.
+CREATE INDEX IF NOT EXISTS move_game_id_idx ON Move (game_id, game_round);
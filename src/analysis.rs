@@ -0,0 +1,261 @@
+use crate::db::{position_hash, Game, InsertionError, Move as GameMove};
+use shakmaty::{san::{San, SanPlus}, uci::Uci, zobrist::Zobrist64, Chess, Move, Role, Square};
+
+/// A move tried on the analysis board.
+#[derive(Debug, Clone)]
+pub struct AnalysisNode {
+  pub parent: Option<usize>,
+  pub mov: Move,
+  pub san_plus: SanPlus,
+  /// Position after the move.
+  pub position: Chess,
+  pub board_hash: Zobrist64,
+  pub children: Vec<usize>,
+  /// Games reaching the position, with the move they played from it, once looked up.
+  pub games: Option<Vec<(GameMove, Game)>>,
+}
+
+/// Variations tried from a position of a game, each move being a node of the tree.
+#[derive(Debug, Clone)]
+pub struct Analysis {
+  /// Plies of the game played before the analysis starts.
+  pub root_ply: usize,
+  pub root: Chess,
+  pub root_children: Vec<usize>,
+  pub nodes: Vec<AnalysisNode>,
+  /// Node of the position shown, `None` being the root.
+  pub current: Option<usize>,
+  /// Nodes left by `undo`, most recent last.
+  redo: Vec<usize>,
+}
+
+impl Analysis {
+  pub fn new(root_ply: usize, root: Chess) -> Self {
+    Analysis { root_ply, root, root_children: Vec::new(), nodes: Vec::new(), current: None, redo: Vec::new() }
+  }
+
+  pub fn position(&self) -> &Chess {
+    match self.current {
+      Some(node) => &self.nodes[node].position,
+      None => &self.root,
+    }
+  }
+
+  pub fn current_node(&self) -> Option<&AnalysisNode> {
+    self.current.map(|node| &self.nodes[node])
+  }
+
+  pub fn children(&self, node: Option<usize>) -> &[usize] {
+    match node {
+      Some(node) => &self.nodes[node].children,
+      None => &self.root_children,
+    }
+  }
+
+  /// Plays a legal move from the current position, going to its node if it was already tried.
+  pub fn play(&mut self, mov: Move) -> usize {
+    let existing = self.children(self.current).iter().copied().find(|child| self.nodes[*child].mov == mov);
+    let node = match existing {
+      Some(node) => node,
+      None => {
+        let mut position = self.position().clone();
+        let san_plus = SanPlus::from_move_and_play_unchecked(&mut position, &mov);
+        let board_hash = position_hash(&position);
+        self.nodes.push(AnalysisNode {
+          parent: self.current,
+          mov,
+          san_plus,
+          position,
+          board_hash,
+          children: Vec::new(),
+          games: None,
+        });
+        let node = self.nodes.len() - 1;
+        match self.current {
+          Some(parent) => self.nodes[parent].children.push(node),
+          None => self.root_children.push(node),
+        }
+        node
+      }
+    };
+    if self.redo.last() == Some(&node) {
+      self.redo.pop();
+    } else {
+      self.redo.clear();
+    }
+    self.current = Some(node);
+    node
+  }
+
+  pub fn undo(&mut self) -> bool {
+    match self.current {
+      Some(node) => {
+        self.redo.push(node);
+        self.current = self.nodes[node].parent;
+        true
+      },
+      None => false,
+    }
+  }
+
+  pub fn redo(&mut self) -> bool {
+    match self.redo.pop() {
+      Some(node) => {
+        self.current = Some(node);
+        true
+      },
+      None => false,
+    }
+  }
+
+  /// Nodes from the root to the current one.
+  pub fn line(&self) -> Vec<usize> {
+    let mut line = Vec::new();
+    let mut node = self.current;
+    while let Some(index) = node {
+      line.push(index);
+      node = self.nodes[index].parent;
+    }
+    line.reverse();
+    line
+  }
+
+  /// Current line from the root, like `3... Nf6 4. O-O`.
+  pub fn line_description(&self) -> String {
+    let line = self.line();
+    let moves: Vec<String> = line.iter().enumerate()
+      .map(|(depth, node)| {
+        let game_round = self.root_ply + depth + 1;
+        let san_plus = &self.nodes[*node].san_plus;
+        match (game_round % 2 == 1, depth) {
+          (true, _) => format!("{}. {san_plus}", (game_round + 1) / 2),
+          (false, 0) => format!("{}... {san_plus}", game_round / 2),
+          (false, _) => format!("{san_plus}"),
+        }
+      })
+      .collect();
+    moves.join(" ")
+  }
+
+  /// Stores the games looked up for every node reaching the position.
+  pub fn set_games(&mut self, board_hash: Zobrist64, games: Vec<(GameMove, Game)>) {
+    for node in self.nodes.iter_mut().filter(|node| node.board_hash == board_hash) {
+      node.games = Some(games.clone());
+    }
+  }
+}
+
+/// Parses a move in SAN, like `Nf3`, or in UCI, like `g1f3`.
+pub fn parse_move(position: &Chess, text: &str) -> Result<Move, InsertionError> {
+  let text = text.trim();
+  let from_san = San::from_ascii(text.as_bytes()).ok().and_then(|san| san.to_move(position).ok());
+  let from_uci = || Uci::from_ascii(text.as_bytes()).ok().and_then(|uci| uci.to_move(position).ok());
+  from_san.or_else(from_uci).ok_or_else(|| InsertionError::InvalidPosition(format!("illegal move: {text}")))
+}
+
+/// The legal move from one square to another, promoting to a queen.
+pub fn move_between(position: &Chess, from: Square, to: Square) -> Option<Move> {
+  [None, Some(Role::Queen)].into_iter()
+    .find_map(|promotion| Uci::Normal { from, to, promotion }.to_move(position).ok())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use shakmaty::Position;
+
+  fn parse_position(moves: &str) -> Chess {
+    let mut position = Chess::default();
+    for text in moves.split_whitespace() {
+      let mov = parse_move(&position, text).unwrap();
+      position.play_unchecked(&mov);
+    }
+    position
+  }
+
+  fn play(analysis: &mut Analysis, text: &str) -> usize {
+    let mov = parse_move(analysis.position(), text).unwrap();
+    analysis.play(mov)
+  }
+
+  fn sans(analysis: &Analysis, nodes: &[usize]) -> Vec<String> {
+    nodes.iter().map(|node| analysis.nodes[*node].san_plus.to_string()).collect()
+  }
+
+  #[test]
+  fn undone_moves_are_redone_in_order() {
+    let mut analysis = Analysis::new(0, Chess::default());
+    play(&mut analysis, "e4");
+    play(&mut analysis, "e5");
+    play(&mut analysis, "Nf3");
+    assert!(analysis.undo() && analysis.undo());
+    assert_eq!(analysis.line_description(), "1. e4");
+    assert!(analysis.redo());
+    assert_eq!(analysis.line_description(), "1. e4 e5");
+    assert!(analysis.redo());
+    assert!(!analysis.redo());
+    assert_eq!(analysis.line_description(), "1. e4 e5 2. Nf3");
+  }
+
+  #[test]
+  fn undo_stops_at_the_root() {
+    let mut analysis = Analysis::new(0, Chess::default());
+    assert!(!analysis.undo());
+    play(&mut analysis, "d4");
+    assert!(analysis.undo());
+    assert_eq!(analysis.current, None);
+    assert!(!analysis.undo());
+  }
+
+  #[test]
+  fn replaying_the_undone_move_keeps_the_rest_to_redo() {
+    let mut analysis = Analysis::new(0, Chess::default());
+    let e4 = play(&mut analysis, "e4");
+    play(&mut analysis, "c5");
+    analysis.undo();
+    analysis.undo();
+    assert_eq!(play(&mut analysis, "e4"), e4);
+    assert!(analysis.redo());
+    assert_eq!(analysis.line_description(), "1. e4 c5");
+  }
+
+  #[test]
+  fn a_new_move_starts_a_variation_and_drops_the_redo_stack() {
+    let mut analysis = Analysis::new(0, Chess::default());
+    play(&mut analysis, "e4");
+    play(&mut analysis, "c5");
+    analysis.undo();
+    play(&mut analysis, "e5");
+    assert!(!analysis.redo());
+    let e4 = analysis.line()[0];
+    assert_eq!(sans(&analysis, analysis.children(Some(e4))), ["c5", "e5"]);
+    assert_eq!(analysis.nodes.len(), 3);
+  }
+
+  #[test]
+  fn describes_lines_from_a_black_move() {
+    let mut analysis = Analysis::new(1, parse_position("e4"));
+    play(&mut analysis, "c5");
+    play(&mut analysis, "Nf3");
+    assert_eq!(analysis.line_description(), "1... c5 2. Nf3");
+  }
+
+  #[test]
+  fn parses_san_and_uci() {
+    let position = Chess::default();
+    assert_eq!(parse_move(&position, "Nf3").unwrap(), parse_move(&position, " g1f3 ").unwrap());
+    assert!(parse_move(&position, "Nf6").is_err());
+    assert!(parse_move(&position, "e2e5").is_err());
+    assert!(parse_move(&position, "hello").is_err());
+  }
+
+  #[test]
+  fn moves_between_squares_promote_to_a_queen() {
+    let position = Chess::default();
+    assert_eq!(move_between(&position, Square::E2, Square::E4), parse_move(&position, "e4").ok());
+    assert_eq!(move_between(&position, Square::E2, Square::E5), None);
+    let position = parse_position("h4 g5 hxg5 h6 gxh6 Bg7 hxg7 Nf6");
+    let promotion = move_between(&position, Square::G7, Square::H8).unwrap();
+    assert_eq!(promotion.promotion(), Some(Role::Queen));
+  }
+}
@@ -0,0 +1,138 @@
+/// Time control of a game, like `180+2`, in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeControl {
+  pub initial: i32,
+  pub increment: i32,
+}
+
+impl TimeControl {
+  /// Parses a `TimeControl` tag like `180+2`, in seconds, `-` being no time control.
+  pub fn parse(tag: &str) -> Option<Self> {
+    let (initial, increment) = tag.trim().split_once('+').unwrap_or((tag.trim(), "0"));
+    let initial = initial.parse::<i32>().ok()?;
+    let increment = increment.parse::<i32>().ok()?;
+    Some(TimeControl { initial: initial * 1000, increment: increment * 1000 })
+  }
+}
+
+/// Parses the clock command of a move comment, like `[%clk 0:02:59.5]`, into milliseconds.
+pub fn parse_clock(comment: &str) -> Option<i32> {
+  let (_, rest) = comment.split_once("[%clk")?;
+  let (clock, _) = rest.split_once(']')?;
+  let mut fields = clock.trim().rsplit(':');
+  let seconds = fields.next()?.parse::<f64>().ok()?;
+  let minutes = fields.next().map_or(Some(0), |minutes| minutes.parse::<i32>().ok())?;
+  let hours = fields.next().map_or(Some(0), |hours| hours.parse::<i32>().ok())?;
+  Some((hours * 3600 + minutes * 60) * 1000 + (seconds * 1000.0).round() as i32)
+}
+
+/// A clock reading like `2:59` or `1:02:03`, with tenths under ten seconds.
+pub fn format_clock(millis: i32) -> String {
+  let millis = millis.max(0);
+  let seconds = millis / 1000;
+  match (seconds / 3600, seconds % 3600 / 60, seconds % 60) {
+    (0, 0, secs) if secs < 10 => format!("0:0{secs}.{}", millis % 1000 / 100),
+    (0, mins, secs) => format!("{mins}:{secs:02}"),
+    (hours, mins, secs) => format!("{hours}:{mins:02}:{secs:02}"),
+  }
+}
+
+/// A duration like `3s` or `1m05`, short enough for the movement list.
+pub fn format_spent(millis: i32) -> String {
+  let seconds = millis.max(0) / 1000;
+  if seconds < 60 {
+    format!("{seconds}s")
+  } else {
+    format!("{}m{:02}", seconds / 60, seconds % 60)
+  }
+}
+
+/// Clocks of a game, as left by each of its moves.
+#[derive(Debug, Clone, Default)]
+pub struct GameClocks {
+  pub time_control: Option<TimeControl>,
+  /// Time left to the player who moved, after each ply, if it was recorded.
+  pub clocks: Vec<Option<i32>>,
+}
+
+impl GameClocks {
+  pub fn is_empty(&self) -> bool {
+    self.clocks.iter().all(Option::is_none)
+  }
+
+  /// Time left to the player who moved at `ply` once they did, `0` being the start of the game.
+  fn after(&self, ply: usize) -> Option<i32> {
+    match ply.checked_sub(1) {
+      Some(index) => self.clocks.get(index).copied().flatten(),
+      None => self.time_control.map(|time_control| time_control.initial),
+    }
+  }
+
+  /// Time left to white and black once `ply` plies were played.
+  pub fn remaining(&self, ply: usize) -> (Option<i32>, Option<i32>) {
+    let (white_ply, black_ply) = if ply % 2 == 1 { (ply, ply - 1) } else { (ply.saturating_sub(1), ply) };
+    (self.after(white_ply), self.after(black_ply))
+  }
+
+  /// Time the player took for the move of `ply`, counting from 1, increment included.
+  pub fn spent(&self, ply: usize) -> Option<i32> {
+    if ply == 0 {
+      return None;
+    }
+    let before = self.after(ply.saturating_sub(2))?;
+    let after = self.after(ply)?;
+    let increment = self.time_control.map_or(0, |time_control| time_control.increment);
+    Some((before - after + increment).max(0))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_clock_comments() {
+    assert_eq!(parse_clock("[%clk 0:02:59.5]"), Some(179_500));
+    assert_eq!(parse_clock("[%eval 0.3] [%clk 1:02:03]"), Some(3_723_000));
+    assert_eq!(parse_clock("[%clk 4:05]"), Some(245_000));
+    assert_eq!(parse_clock("[%clk 9.8]"), Some(9_800));
+    assert_eq!(parse_clock("good move"), None);
+    assert_eq!(parse_clock("[%clk 0:xx:10]"), None);
+    assert_eq!(parse_clock("[%clk 0:02:59"), None);
+  }
+
+  #[test]
+  fn parses_time_controls() {
+    assert_eq!(TimeControl::parse("180+2"), Some(TimeControl { initial: 180_000, increment: 2_000 }));
+    assert_eq!(TimeControl::parse(" 600 "), Some(TimeControl { initial: 600_000, increment: 0 }));
+    assert_eq!(TimeControl::parse("-"), None);
+    assert_eq!(TimeControl::parse("40/7200:3600"), None);
+  }
+
+  fn blitz(clocks: Vec<Option<i32>>) -> GameClocks {
+    GameClocks { time_control: TimeControl::parse("180+2"), clocks }
+  }
+
+  #[test]
+  fn remaining_time_follows_the_side_that_moved() {
+    let clocks = blitz(vec![Some(181_000), Some(175_000), Some(170_000), None]);
+    assert_eq!(clocks.remaining(0), (Some(180_000), Some(180_000)));
+    assert_eq!(clocks.remaining(1), (Some(181_000), Some(180_000)));
+    assert_eq!(clocks.remaining(2), (Some(181_000), Some(175_000)));
+    assert_eq!(clocks.remaining(3), (Some(170_000), Some(175_000)));
+    assert_eq!(clocks.remaining(4), (Some(170_000), None));
+  }
+
+  #[test]
+  fn spent_time_includes_the_increment() {
+    let clocks = blitz(vec![Some(181_000), Some(175_000), Some(170_000), None]);
+    assert_eq!(clocks.spent(0), None);
+    assert_eq!(clocks.spent(1), Some(1_000));
+    assert_eq!(clocks.spent(2), Some(7_000));
+    assert_eq!(clocks.spent(3), Some(13_000));
+    assert_eq!(clocks.spent(4), None);
+    let untimed = GameClocks { time_control: None, clocks: vec![Some(60_000), Some(60_000), Some(55_000)] };
+    assert_eq!(untimed.spent(1), None);
+    assert_eq!(untimed.spent(3), Some(5_000));
+  }
+}
@@ -0,0 +1,210 @@
+use crate::theme::{PieceSet, ThemeName};
+
+/// A command typed in the command line opened with `:`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+  /// Opens the profile of a player.
+  Player(String),
+  /// Opens a game by its id.
+  Game(i32),
+  /// Lists the games reaching a position.
+  Fen(String),
+  /// Lists the games reaching a piece placement pattern, like `Nd5 pd6 !b@light`.
+  Pattern(String),
+  /// Jumps to a ply of the game shown.
+  Ply(usize),
+  /// Flips the board shown.
+  Flip,
+  /// Writes the game shown as PGN, to the given file or to one named after the game.
+  ExportPgn(Option<String>),
+  Theme(ThemeName),
+  Pieces(PieceSet),
+  Quit,
+}
+
+/// Every command, with what it expects after its name.
+pub const COMMANDS: [(&str, &str); 10] = [
+  ("player", "<name>"),
+  ("game", "<id>"),
+  ("fen", "<fen>"),
+  ("pattern", "<pieces>"),
+  ("ply", "<ply>"),
+  ("flip", ""),
+  ("export", "pgn [file]"),
+  ("theme", "<colors>"),
+  ("pieces", "<set>"),
+  ("quit", ""),
+];
+
+impl Command {
+  /// Parses a command line like `ply 24` or `:theme wood`, the command name being allowed to be
+  /// shortened as long as only one command starts like it.
+  pub fn parse(line: &str) -> Result<Self, String> {
+    let line = line.trim().trim_start_matches(':').trim_start();
+    let (name, argument) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let argument = argument.trim();
+    let matching: Vec<&str> = COMMANDS.iter()
+      .map(|(command, _)| *command)
+      .filter(|command| command.starts_with(name))
+      .collect();
+    let command = match matching[..] {
+      _ if name.is_empty() => return Err("Type a command, Tab completes it".to_string()),
+      _ if matching.contains(&name) => name,
+      [command] => command,
+      [] => return Err(format!("Unknown command {name}")),
+      _ => return Err(format!("{name} could be any of: {}", matching.join(", "))),
+    };
+    match command {
+      "player" => required(argument, "a player name").map(|name| Command::Player(name.to_string())),
+      "game" => argument.parse().map(Command::Game).map_err(|_| "Game id must be a number".to_string()),
+      "fen" => required(argument, "a FEN").map(|fen| Command::Fen(fen.to_string())),
+      "pattern" => required(argument, "a pattern").map(|pattern| Command::Pattern(pattern.to_string())),
+      "ply" => argument.parse().map(Command::Ply).map_err(|_| "Ply must be a number".to_string()),
+      "flip" => Ok(Command::Flip),
+      "export" => {
+        let (format, file) = argument.split_once(char::is_whitespace).unwrap_or((argument, ""));
+        match format {
+          "pgn" => Ok(Command::ExportPgn(Some(file.trim().to_string()).filter(|file| !file.is_empty()))),
+          "" => Err("Export needs a format: pgn".to_string()),
+          format => Err(format!("Unknown export format {format}, only pgn is supported")),
+        }
+      },
+      "theme" => named(argument, "theme", &ThemeName::ALL, ThemeName::name).map(Command::Theme),
+      "pieces" => named(argument, "piece set", &PieceSet::ALL, PieceSet::name).map(Command::Pieces),
+      "quit" => Ok(Command::Quit),
+      _ => unreachable!("{command} is listed in COMMANDS but never parsed"),
+    }
+  }
+}
+
+fn required<'a>(argument: &'a str, what: &str) -> Result<&'a str, String> {
+  if argument.is_empty() {
+    Err(format!("Expected {what}"))
+  } else {
+    Ok(argument)
+  }
+}
+
+fn named<T: Copy>(argument: &str, what: &str, all: &[T], name: fn(T) -> &'static str) -> Result<T, String> {
+  all.iter().copied()
+    .find(|value| name(*value).eq_ignore_ascii_case(argument))
+    .ok_or_else(|| {
+      let names: Vec<&str> = all.iter().map(|value| name(*value)).collect();
+      format!("Unknown {what} {argument:?}, expected one of: {}", names.join(", "))
+    })
+}
+
+/// A way to go on with a command line, with what is left to type after it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Completion {
+  pub line: String,
+  pub hint: &'static str,
+}
+
+/// Lines `line` can be completed into from the command names and the arguments known beforehand,
+/// players being left to the database.
+pub fn completions(line: &str) -> Vec<Completion> {
+  let line = line.trim_start().trim_start_matches(':');
+  let Some((name, argument)) = line.split_once(' ') else {
+    return COMMANDS.iter()
+      .filter(|(command, _)| command.starts_with(line))
+      .map(|(command, hint)| Completion {
+        line: if hint.is_empty() { command.to_string() } else { format!("{command} ") },
+        hint,
+      })
+      .collect();
+  };
+  let values: Vec<&str> = match name {
+    "theme" => ThemeName::ALL.iter().map(|theme| theme.name()).collect(),
+    "pieces" => PieceSet::ALL.iter().map(|set| set.name()).collect(),
+    "export" => vec!["pgn"],
+    _ => Vec::new(),
+  };
+  values.into_iter()
+    .filter(|value| value.starts_with(argument.trim_start()))
+    .map(|value| Completion { line: format!("{name} {value}"), hint: "" })
+    .collect()
+}
+
+/// Longest start shared by every completion, which the command line can be completed to.
+pub fn common_prefix(completions: &[Completion]) -> Option<String> {
+  let (first, rest) = completions.split_first()?;
+  let mut prefix = first.line.as_str();
+  for completion in rest {
+    let shared = prefix.char_indices()
+      .zip(completion.line.chars())
+      .find(|((_, a), b)| a != b)
+      .map_or(prefix.len().min(completion.line.len()), |((index, _), _)| index);
+    prefix = &prefix[..shared];
+  }
+  Some(prefix.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn shortened_names_must_be_unambiguous() {
+    assert_eq!(Command::parse("q"), Ok(Command::Quit));
+    assert_eq!(Command::parse("fl"), Ok(Command::Flip));
+    assert_eq!(Command::parse("pla alice"), Ok(Command::Player("alice".to_string())));
+    assert_eq!(Command::parse("pl 3"), Err("pl could be any of: player, ply".to_string()));
+    assert_eq!(Command::parse("ply 3"), Ok(Command::Ply(3)));
+    assert_eq!(Command::parse("undo"), Err("Unknown command undo".to_string()));
+    assert!(Command::parse("  ").is_err());
+  }
+
+  #[test]
+  fn every_command_parses() {
+    for (name, hint) in COMMANDS {
+      let argument = match hint {
+        "<id>" | "<ply>" => "1",
+        "<colors>" => "wood",
+        "<set>" => "ascii",
+        "pgn [file]" => "pgn",
+        "" => "",
+        _ => "x",
+      };
+      assert!(Command::parse(&format!("{name} {argument}")).is_ok(), "{name} {argument}");
+    }
+  }
+
+  #[test]
+  fn parses_arguments() {
+    assert_eq!(Command::parse(":game 12"), Ok(Command::Game(12)));
+    assert_eq!(Command::parse("game twelve"), Err("Game id must be a number".to_string()));
+    assert_eq!(Command::parse("player"), Err("Expected a player name".to_string()));
+    assert_eq!(Command::parse("fen  8/8/8/8/8/8/8/8 w - - "), Ok(Command::Fen("8/8/8/8/8/8/8/8 w - -".to_string())));
+    assert_eq!(Command::parse("export pgn"), Ok(Command::ExportPgn(None)));
+    assert_eq!(Command::parse("export pgn games/a.pgn"), Ok(Command::ExportPgn(Some("games/a.pgn".to_string()))));
+    assert!(Command::parse("export").is_err());
+    assert!(Command::parse("export csv").is_err());
+    assert_eq!(Command::parse("theme WOOD"), Ok(Command::Theme(ThemeName::Wood)));
+    assert_eq!(Command::parse("pieces figurine"), Ok(Command::Pieces(PieceSet::Figurine)));
+    assert!(Command::parse("theme neon").is_err());
+  }
+
+  fn lines(completions: &[Completion]) -> Vec<&str> {
+    completions.iter().map(|completion| completion.line.as_str()).collect()
+  }
+
+  #[test]
+  fn completes_names_and_known_arguments() {
+    assert_eq!(lines(&completions("p")), ["player ", "pattern ", "ply ", "pieces "]);
+    assert_eq!(lines(&completions(":fl")), ["flip"]);
+    assert_eq!(lines(&completions("theme ")).len(), ThemeName::ALL.len());
+    assert_eq!(lines(&completions("theme d")), ["theme dark"]);
+    assert_eq!(lines(&completions("export ")), ["export pgn"]);
+    assert!(completions("player al").is_empty());
+    assert!(completions("x").is_empty());
+  }
+
+  #[test]
+  fn completes_to_the_shared_prefix() {
+    assert_eq!(common_prefix(&completions("pl")), Some("pl".to_string()));
+    assert_eq!(common_prefix(&completions("pa")), Some("pattern ".to_string()));
+    assert_eq!(common_prefix(&completions("pieces f")), Some("pieces fi".to_string()));
+    assert_eq!(common_prefix(&[]), None);
+  }
+}
@@ -1,7 +1,8 @@
 use futures_util::stream::FuturesUnordered;
 use futures_util::StreamExt;
-use shakmaty::{Chess, Position, zobrist::{ZobristHash, Zobrist64}};
-use pgn_reader::{RawHeader, SanPlus, Visitor, BufferedReader};
+use shakmaty::{Chess, Position, CastlingMode, Board, Role, ByRole, Color, fen::Fen, zobrist::{ZobristHash, ZobristValue, Zobrist64}};
+use pgn_reader::{RawComment, RawHeader, SanPlus, Skip, Visitor, BufferedReader};
+use crate::clock::parse_clock;
 use sqlx::{types::chrono::{NaiveDate, NaiveTime, NaiveDateTime}, PgPool};
 use sqlx::Error as DbErr;
 use kdam::BarExt;
@@ -15,9 +16,132 @@ pub struct Game {
   pub white: String,
   pub black: String,
   pub white_elo: Option<i32>,
-  pub black_elo: Option<i32>
+  pub black_elo: Option<i32>,
+  pub result: Option<String>,
+  pub plies: Option<i32>,
+}
+
+/// Tags of a game shown only in its header.
+#[derive(Debug, Clone, Default)]
+pub struct GameTags {
+  pub site: Option<String>,
+  pub eco: Option<String>,
+  pub opening: Option<String>,
+  pub time_control: Option<String>,
+  pub termination: Option<String>,
+}
+
+
+/// Column by which a player's game list is sorted. `Elo` and `Result` are the opponent's rating
+/// and the player's score.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GameOrder {
+  Date,
+  Elo,
+  Opponent,
+  Result,
+  Length,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GameSortKey {
+  Date(NaiveDateTime),
+  Number(i32),
+  Text(String),
+}
+
+/// Sort key and id of the last game of a page, from where the next page continues.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameCursor {
+  pub key: GameSortKey,
+  pub id: i32,
+}
+
+#[derive(Debug, Clone)]
+pub struct GameListQuery {
+  pub order: GameOrder,
+  pub descending: bool,
+  /// Substring of the opponent's name or of the event.
+  pub filter: String,
+  /// Only list games against this player.
+  pub opponent: Option<String>,
+}
+
+/// Order of the games listed under each position of the game being viewed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RelatedOrder {
+  /// Average rating of both players, highest first.
+  Elo,
+  /// Most recent first.
+  Date,
+}
+
+/// Which games are listed as reaching the positions of the game being viewed.
+#[derive(Debug, Clone)]
+pub struct RelatedGamesOptions {
+  /// Positions reached in fewer plies are skipped, as most games go through them.
+  pub min_ply: i32,
+  /// Games listed per position.
+  pub limit: i64,
+  pub order: RelatedOrder,
+}
+
+impl Default for RelatedGamesOptions {
+  fn default() -> Self {
+    RelatedGamesOptions { min_ply: 6, limit: 20, order: RelatedOrder::Elo }
+  }
+}
+
+/// A game that reached a position of the game being viewed, and the moves it took to get there.
+#[derive(Debug, Clone)]
+pub struct RelatedGame {
+  pub game: Game,
+  /// Move this game played from the position.
+  pub next_move: Move,
+  /// Moves of this game up to the position.
+  pub path: Vec<SAN>,
+}
+
+impl RelatedGame {
+  /// Whether this game reached the position with other moves than `moves`, the ones leading to it in the viewed game.
+  pub fn transposed(&self, moves: &[Move]) -> bool {
+    self.path.len() != moves.len() || self.path.iter().zip(moves)
+      .any(|(san, mvmt)| format!("{}", san.0) != format!("{}", mvmt.san_plus.0))
+  }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ColorScore {
+  pub games: i64,
+  pub wins: i64,
+  pub draws: i64,
+  pub losses: i64,
 }
 
+/// Aggregate statistics of a player, shown before their game list.
+#[derive(Debug, Clone, Default)]
+pub struct PlayerProfile {
+  pub games: i64,
+  pub as_white: ColorScore,
+  pub as_black: ColorScore,
+  /// Average rating per month.
+  pub rating_history: Vec<(NaiveDateTime, i32)>,
+  pub opponents: Vec<(String, i64)>,
+  pub white_openings: Vec<(String, i64)>,
+  pub black_openings: Vec<(String, i64)>,
+  pub average_plies: Option<f64>,
+  pub median_plies: Option<f64>,
+}
+
+/// Games between two players, from the point of view of the first one.
+#[derive(Debug, Clone, Default)]
+pub struct HeadToHead {
+  pub as_white: ColorScore,
+  pub as_black: ColorScore,
+  pub openings: Vec<(String, i64)>,
+  /// Date of each game, with its result for the first player: `W`, `D`, `L` or `?`.
+  pub timeline: Vec<(NaiveDateTime, char)>,
+}
 
 #[derive(sqlx::Type, Debug, Clone, sqlx::FromRow, PartialEq)]
 pub struct GameId {
@@ -30,6 +154,7 @@ pub enum InsertionError {
   ParsingError,
   IncompleteDataError(String),
   IlegalMove(SanPlus),
+  InvalidPosition(String),
   IoError(std::io::Error)
 }
 
@@ -43,7 +168,15 @@ pub struct PGNParser {
   black: Option<String>,
   white_elo: Option<i32>,
   black_elo: Option<i32>,
+  result: Option<String>,
+  eco: Option<String>,
+  opening: Option<String>,
+  time_control: Option<String>,
+  site: Option<String>,
+  termination: Option<String>,
   moves: Vec<SAN>,
+  /// Clock left after each move, in milliseconds, from its `[%clk]` comment.
+  clocks: Vec<Option<i32>>,
 }
 
 #[derive(Debug)]
@@ -55,7 +188,14 @@ pub struct ParsedChessGame {
   pub black: String,
   pub white_elo: Option<i32>,
   pub black_elo: Option<i32>,
+  pub result: Option<String>,
+  pub eco: Option<String>,
+  pub opening: Option<String>,
+  pub time_control: Option<String>,
+  pub site: Option<String>,
+  pub termination: Option<String>,
   moves: Vec<SAN>,
+  clocks: Vec<Option<i32>>,
 }
 
 #[derive(Debug, Clone)]
@@ -75,6 +215,26 @@ impl sqlx::Type<sqlx::Postgres> for SAN {
   }
 }
 
+/// How often a position occurs in the database, and how the games that reached it ended.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PositionStats {
+  pub board_hash: i64,
+  pub occurrences: i64,
+  pub games: i64,
+  pub white_wins: i64,
+  pub draws: i64,
+  pub black_wins: i64,
+}
+
+/// A game that reached a material signature, and how it got there.
+#[derive(Debug, Clone)]
+pub struct SignatureReach {
+  pub game: Game,
+  pub first_round: i32,
+  pub plies: i64,
+  pub entry_move: Option<Move>,
+}
+
 #[derive(Debug, Clone)]
 pub struct Move {
   pub board: Zobrist64, // board id
@@ -83,6 +243,184 @@ pub struct Move {
   pub game_round: i32
 }
 
+impl Move {
+  /// Move number followed by the SAN, e.g. `12 Nf3` or `12 ... Nc6`.
+  pub fn numbered_san(&self) -> String {
+    if self.game_round % 2 == 1 {
+      format!("{} {}", (self.game_round + 1) / 2, self.san_plus.0)
+    } else {
+      format!("{} ... {}", self.game_round / 2, self.san_plus.0)
+    }
+  }
+}
+
+/// A PGN tag pair line, like `[White "alice"]`, with the quotes and backslashes of the value escaped.
+pub fn pgn_tag(tag: &str, value: &str) -> String {
+  format!("[{tag} \"{}\"]\n", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// A game as PGN, with the tags it was stored with and its moves.
+pub fn game_pgn(game: &Game, tags: &GameTags, moves: &[Move]) -> String {
+  let result = game.result.as_deref().unwrap_or("*");
+  let mut headers = vec![
+    ("Event", game.event.clone()),
+    ("Site", tags.site.clone().unwrap_or_else(|| "?".to_string())),
+    ("Date", game.datetime.format("%Y.%m.%d").to_string()),
+    ("White", game.white.clone()),
+    ("Black", game.black.clone()),
+    ("Result", result.to_string()),
+    ("UTCDate", game.datetime.format("%Y.%m.%d").to_string()),
+    ("UTCTime", game.datetime.format("%H:%M:%S").to_string()),
+  ];
+  let optional = [
+    ("WhiteElo", game.white_elo.map(|elo| elo.to_string())),
+    ("BlackElo", game.black_elo.map(|elo| elo.to_string())),
+    ("ECO", tags.eco.clone()),
+    ("Opening", tags.opening.clone()),
+    ("TimeControl", tags.time_control.clone()),
+    ("Termination", tags.termination.clone()),
+  ];
+  headers.extend(optional.into_iter().filter_map(|(tag, value)| Some((tag, value?))));
+  let mut pgn: String = headers.iter()
+    .map(|(tag, value)| pgn_tag(tag, value))
+    .collect();
+  pgn.push('\n');
+  for movement in moves {
+    if movement.game_round % 2 == 1 {
+      pgn.push_str(&format!("{}. ", (movement.game_round + 1) / 2));
+    }
+    pgn.push_str(&format!("{} ", movement.san_plus.0));
+  }
+  pgn.push_str(result);
+  pgn.push('\n');
+  pgn
+}
+
+/// Hashes a position the same way it is stored in the `Move` table.
+pub fn position_hash(position: &Chess) -> Zobrist64 {
+  position.zobrist_hash::<Zobrist64>(shakmaty::EnPassantMode::Legal)
+}
+
+/// Hashes only the pawns and the side to move, so positions sharing a pawn structure collide.
+pub fn pawn_structure_hash(position: &Chess) -> Zobrist64 {
+  let board = position.board();
+  let mut hash = Zobrist64::default();
+  for square in board.pawns() {
+    if let Some(piece) = board.piece_at(square) {
+      hash ^= Zobrist64::zobrist_for_piece(square, piece);
+    }
+  }
+  if position.turn() == Color::White {
+    hash ^= Zobrist64::zobrist_for_white_turn();
+  }
+  hash
+}
+
+/// Bitboards stored with each position: white, black, pawns, knights, bishops, rooks, queens and kings.
+pub fn position_bitboards(board: &Board) -> [i64; 8] {
+  [board.white(), board.black(), board.pawns(), board.knights(), board.bishops(), board.rooks(), board.queens(), board.kings()]
+    .map(|bitboard| bitboard.0 as i64)
+}
+
+pub fn position_from_fen(fen: &str) -> Result<Chess, InsertionError> {
+  let fen: Fen = fen.trim().parse()
+    .map_err(|err| InsertionError::InvalidPosition(format!("{err}")))?;
+  fen.into_position(CastlingMode::Standard)
+    .map_err(|err| InsertionError::InvalidPosition(format!("{err}")))
+}
+
+/// Parses a FEN or an EPD line, ignoring move counters and EPD operations.
+pub fn position_from_epd(epd: &str) -> Result<Chess, InsertionError> {
+  let fields: Vec<&str> = epd.split_whitespace().take(4).collect();
+  position_from_fen(&fields.join(" "))
+}
+
+const MATERIAL_ORDER: [Role; 6] = [Role::King, Role::Queen, Role::Rook, Role::Bishop, Role::Knight, Role::Pawn];
+
+fn material_side_signature(side: &ByRole<u8>) -> String {
+  MATERIAL_ORDER.iter()
+    .map(|role| role.upper_char().to_string().repeat(*side.get(*role) as usize))
+    .collect()
+}
+
+/// Material signature of a board, like `KRPvKR`: white pieces, then black ones.
+pub fn material_signature(board: &Board) -> String {
+  let material = board.material();
+  format!("{}v{}", material_side_signature(&material.white), material_side_signature(&material.black))
+}
+
+/// Parses a signature like `krp v kr` into its canonical form and the one with colors swapped.
+pub fn material_signatures(signature: &str) -> Result<[String; 2], InsertionError> {
+  let invalid = || InsertionError::InvalidPosition(format!("invalid material signature: {signature}"));
+  let normalized = signature.replace(' ', "").to_ascii_uppercase();
+  let (white, black) = normalized.split_once('V').ok_or_else(invalid)?;
+  let mut sides = [ByRole::<u8>::default(), ByRole::<u8>::default()];
+  for (side, pieces) in sides.iter_mut().zip([white, black]) {
+    for piece in pieces.chars() {
+      let role = Role::from_char(piece.to_ascii_lowercase()).ok_or_else(invalid)?;
+      *side.get_mut(role) += 1;
+    }
+    // stored signatures always have both kings, so any other count would match nothing.
+    if side.king != 1 {
+      return Err(InsertionError::InvalidPosition(format!("each side needs exactly one king: {signature}")));
+    }
+  }
+  let [white, black] = sides.map(|side| material_side_signature(&side));
+  Ok([format!("{white}v{black}"), format!("{black}v{white}")])
+}
+
+/// Pieces each side starts with.
+const STARTING_MATERIAL: ByRole<u8> = ByRole { pawn: 8, knight: 2, bishop: 2, rook: 2, queen: 1, king: 1 };
+
+/// Value of a piece in pawns, the king being worth nothing as it is never traded.
+fn role_value(role: Role) -> i32 {
+  match role {
+    Role::Pawn => 1,
+    Role::Knight | Role::Bishop => 3,
+    Role::Rook => 5,
+    Role::Queen => 9,
+    Role::King => 0,
+  }
+}
+
+fn material_value(side: &ByRole<u8>) -> i32 {
+  MATERIAL_ORDER.iter().map(|role| role_value(*role) * *side.get(*role) as i32).sum()
+}
+
+/// Material of white minus the one of black, in pawns.
+pub fn material_balance(board: &Board) -> i32 {
+  let material = board.material();
+  material_value(&material.white) - material_value(&material.black)
+}
+
+/// Pieces of `color` missing from the board since the start, most valuable first.
+///
+/// Pieces beyond the starting ones are counted as promoted pawns rather than captured ones. The
+/// board alone cannot tell a promoted piece from an original one though, so once a promoted piece
+/// is taken, it shows as a captured pawn.
+pub fn captured_pieces(board: &Board, color: Color) -> Vec<Role> {
+  let side = board.material_side(color);
+  let promoted: u8 = MATERIAL_ORDER.iter()
+    .filter(|role| **role != Role::Pawn)
+    .map(|role| side.get(*role).saturating_sub(*STARTING_MATERIAL.get(*role)))
+    .sum();
+  MATERIAL_ORDER.iter()
+    .flat_map(|role| {
+      let mut missing = STARTING_MATERIAL.get(*role).saturating_sub(*side.get(*role));
+      if *role == Role::Pawn {
+        missing = missing.saturating_sub(promoted);
+      }
+      std::iter::repeat(*role).take(missing as usize)
+    })
+    .collect()
+}
+
+impl Default for PGNParser {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
 impl PGNParser {
   pub fn new() -> Self {
     PGNParser {
@@ -93,7 +431,14 @@ impl PGNParser {
       black: None,
       white_elo: None,
       black_elo: None,
+      result: None,
+      eco: None,
+      opening: None,
+      time_control: None,
+      site: None,
+      termination: None,
       moves: Vec::new(),
+      clocks: Vec::new(),
     }
   }
 }
@@ -103,7 +448,6 @@ impl Visitor for PGNParser {
 
   fn header(&mut self, key: &[u8], value: RawHeader<'_>) {
     let val: String = value.decode_utf8_lossy().into();
-    //println!("{}:{val}", std::str::from_utf8(key).unwrap());
     match key {
       b"Event" => self.event = Some(val),
       b"UTCDate" => {
@@ -124,16 +468,33 @@ impl Visitor for PGNParser {
         let elo = val.parse::<i32>().ok();
         self.black_elo = elo;
       }
+      b"Result" => self.result = Some(val),
+      b"ECO" => self.eco = Some(val),
+      b"Opening" => self.opening = Some(val),
+      b"TimeControl" => self.time_control = Some(val),
+      b"Site" => self.site = Some(val),
+      b"Termination" => self.termination = Some(val),
       _ => {}
     }
   }
 
   fn san(&mut self, san: SanPlus) {
     self.moves.push(SAN(san));
+    self.clocks.push(None);
+  }
+
+  fn comment(&mut self, comment: RawComment<'_>) {
+    if let (Some(clock), Some(last)) = (parse_clock(&String::from_utf8_lossy(comment.as_bytes())), self.clocks.last_mut()) {
+      *last = Some(clock);
+    }
+  }
+
+  fn begin_variation(&mut self) -> Skip {
+    Skip(true) // only the moves played are stored, and their clocks.
   }
 
   fn end_game(&mut self) -> Self::Result {
-    let game = std::mem::replace(self, Self::new());
+    let game = std::mem::take(self);
     ParsedChessGame {
       event: game.event.expect("Event missing"),
       date: game.date.expect("Date missing"),
@@ -142,7 +503,14 @@ impl Visitor for PGNParser {
       black: game.black.expect("Black player missing"),
       white_elo: game.white_elo,
       black_elo: game.black_elo,
+      result: game.result,
+      eco: game.eco,
+      opening: game.opening,
+      time_control: game.time_control,
+      site: game.site,
+      termination: game.termination,
       moves: game.moves,
+      clocks: game.clocks,
     }
   }
 }
@@ -155,11 +523,19 @@ impl ParsedChessGame {
     let mut board_hashes = Vec::with_capacity(self.moves.len());
     let mut mvmts = Vec::with_capacity(self.moves.len());
     let mut game_rounds = Vec::with_capacity(self.moves.len());
+    let mut materials = Vec::with_capacity(self.moves.len());
+    let mut pawn_hashes = Vec::with_capacity(self.moves.len());
+    let mut bitboards: [Vec<i64>; 8] = Default::default();
     for (index, movement) in self.moves.into_iter().enumerate() {
       let move_to_play = movement.0.san.to_move(&board)
         .map_err(|_| InsertionError::IlegalMove(movement.0.clone()))?
         .clone();
-      let board_hash = board.zobrist_hash::<Zobrist64>(shakmaty::EnPassantMode::Legal).0;
+      let board_hash = position_hash(&board).0;
+      materials.push(material_signature(board.board()));
+      pawn_hashes.push(pawn_structure_hash(&board).0 as i64);
+      for (column, bitboard) in bitboards.iter_mut().zip(position_bitboards(board.board())) {
+        column.push(bitboard);
+      }
       board.play_unchecked(&move_to_play);
       board_hashes.push(board_hash as i64);
       mvmts.push(format!("{}", movement.0));
@@ -170,12 +546,15 @@ impl ParsedChessGame {
            INSERT INTO Player VALUES ($1), ($2)
            ON CONFLICT DO NOTHING RETURNING player_name
          ), gid AS (
-           INSERT INTO Game (white, black, event, datetime, white_elo, black_elo)
-           VALUES ($1, $2, $3, $4, $5, $6)
+           INSERT INTO Game (white, black, event, datetime, white_elo, black_elo, result, plies, eco, opening, time_control, site, termination)
+           VALUES ($1, $2, $3, $4, $5, $6, $10, $21, $22, $23, $24, $26, $27)
            RETURNING id
          )
-         INSERT INTO Move (game_round, san_plus, board_hash, game_id)
-         SELECT * FROM UNNEST($7::int[], $8::text[], $9::bigint[])
+         INSERT INTO Move (game_round, san_plus, board_hash, material, pawn_hash,
+                           white_bb, black_bb, pawn_bb, knight_bb, bishop_bb, rook_bb, queen_bb, king_bb, clock, game_id)
+         SELECT * FROM UNNEST($7::int[], $8::text[], $9::bigint[], $11::text[], $12::bigint[],
+                              $13::bigint[], $14::bigint[], $15::bigint[], $16::bigint[],
+                              $17::bigint[], $18::bigint[], $19::bigint[], $20::bigint[], $25::int[])
          CROSS JOIN gid"#,
       self.white,
       self.black,
@@ -185,7 +564,25 @@ impl ParsedChessGame {
       self.black_elo,
       &game_rounds,
       &mvmts,
-      &board_hashes)
+      &board_hashes,
+      self.result,
+      &materials,
+      &pawn_hashes,
+      &bitboards[0],
+      &bitboards[1],
+      &bitboards[2],
+      &bitboards[3],
+      &bitboards[4],
+      &bitboards[5],
+      &bitboards[6],
+      &bitboards[7],
+      game_rounds.len() as i32,
+      self.eco,
+      self.opening,
+      self.time_control,
+      &self.clocks as &[Option<i32>],
+      self.site,
+      self.termination)
       .execute(&conn)
       .await?;
     Ok(())
@@ -224,7 +621,47 @@ pub async fn insert_games_from_file(conn: PgPool, file: &str) -> Result<(), Inse
       Err(err) => { pb.write(format!("{err:?}")); }
     }
   }
-  println!("");
+  println!();
   println!("{} games inserted in {:.2} seconds.", games, pb.elapsed_time);
   Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn board(fen: &str) -> Board {
+    position_from_fen(fen).unwrap().board().clone()
+  }
+
+  #[test]
+  fn nothing_is_captured_at_the_start() {
+    let start = Board::default();
+    assert!(captured_pieces(&start, Color::White).is_empty());
+    assert!(captured_pieces(&start, Color::Black).is_empty());
+    assert_eq!(material_balance(&start), 0);
+  }
+
+  #[test]
+  fn captures_are_listed_most_valuable_first() {
+    let board = board("r1b1kbnr/pppp1ppp/8/8/8/8/PPP2PPP/RNBQKBNR w KQkq - 0 1");
+    assert_eq!(captured_pieces(&board, Color::White), [Role::Pawn, Role::Pawn]);
+    assert_eq!(captured_pieces(&board, Color::Black), [Role::Queen, Role::Knight, Role::Pawn]);
+    assert_eq!(material_balance(&board), 11);
+  }
+
+  #[test]
+  fn promoted_pawns_are_not_captured() {
+    let board = board("rQbqkbnr/1ppppppp/8/8/8/8/1PPPPPPP/RNBQKBNR w - - 0 1");
+    assert!(captured_pieces(&board, Color::White).is_empty());
+    assert_eq!(captured_pieces(&board, Color::Black), [Role::Knight, Role::Pawn]);
+    assert_eq!(material_balance(&board), 12);
+  }
+
+  #[test]
+  fn a_captured_promoted_piece_shows_as_a_pawn() {
+    // whether the queen left is the promoted one or the first one, the board looks the same.
+    let board = board("rnbqkbnr/1ppppppp/8/8/8/8/1PPPPPPP/RNBQKBNR w - - 0 1");
+    assert_eq!(captured_pieces(&board, Color::White), [Role::Pawn]);
+  }
+}
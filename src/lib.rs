@@ -1,3 +1,9 @@
 pub mod db;
 pub mod ui;
 pub mod queries;
+pub mod pattern;
+pub mod repertoire;
+pub mod analysis;
+pub mod theme;
+pub mod clock;
+pub mod command;
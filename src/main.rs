@@ -1,9 +1,17 @@
+use std::collections::HashMap;
+use std::future::Future;
 use std::time::Duration;
 
 use log::warn;
-use sqlx::postgres::PgPoolOptions;
-use nixchess::{ui::cli_entrypoint, db::{insert_games_from_file, InsertionError}};
-use clap::{Parser, Subcommand};
+use sqlx::{postgres::PgPoolOptions, PgConnection, Connection};
+use nixchess::{ui::cli_entrypoint, db::{insert_games_from_file, position_from_fen, position_from_epd, position_hash, pawn_structure_hash, material_signatures, RelatedGamesOptions, RelatedOrder, InsertionError, Move, Game, PositionStats, SignatureReach}};
+use nixchess::queries::{movement_and_games_from_position, stats_from_positions, games_from_material, games_from_pawn_structure, games_from_pattern};
+use nixchess::queries::{opening_lines_from_player, novelties_from_player};
+use nixchess::pattern::PiecePattern;
+use nixchess::repertoire::Repertoire;
+use nixchess::theme::{BoardTheme, PieceSet, ThemeName};
+use shakmaty::{EnPassantMode, fen::Epd};
+use clap::{Parser, Subcommand, ValueEnum};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about=None)]
@@ -14,6 +22,27 @@ struct NixChessArgs {
   /// Games database to connect to. If none, uses the `DATABASE_URL` environment variable.
   #[clap(short, long)]
   db_url: Option<String>,
+  /// Only list the games reaching positions from this ply on, while viewing a game
+  #[clap(long, default_value_t = RelatedGamesOptions::default().min_ply)]
+  related_from_ply: i32,
+  /// Games listed per position while viewing a game
+  #[clap(long, default_value_t = RelatedGamesOptions::default().limit)]
+  related_limit: i64,
+  /// Order of the games listed per position while viewing a game
+  #[clap(long, value_enum, default_value_t = RelatedOrderArg::Elo)]
+  related_order: RelatedOrderArg,
+  /// Colors of the board, cycled through with `t` while viewing a game
+  #[clap(long, value_enum, default_value_t = ThemeArg::Classic)]
+  theme: ThemeArg,
+  /// How pieces are drawn, cycled through with `T` while viewing a game
+  #[clap(long, value_enum, default_value_t = PiecesArg::Filled)]
+  pieces: PiecesArg,
+  /// Draw squares twice as large when there is room for them, toggled with `z` while viewing a game
+  #[clap(long)]
+  large_board: bool,
+  /// Positions reached by fewer earlier games count as novelties, while viewing a game
+  #[clap(long, default_value_t = 1)]
+  novelty_min_games: i64,
 }
 
 #[derive(Debug, Subcommand)]
@@ -21,9 +50,213 @@ enum Command {
   /// Fill the database from a pgn file
   Fill {
     pgn_file: String
+  },
+  /// List the games that reached a position, and the moves played from it
+  Position {
+    fen: String
+  },
+  /// List the games that reached the pawn structure of a position, one move per game
+  Pawns {
+    fen: String
+  },
+  /// List the games that reached a piece placement pattern, like `Nd5 pd6 !b@light`, one move per game
+  Pattern {
+    pattern: String
+  },
+  /// Print the opening tree of a player as a PGN with variations
+  Repertoire {
+    player: String,
+    #[clap(short, long, value_enum, default_value_t = PlayerColor::White)]
+    color: PlayerColor,
+    /// Plies to follow from the starting position
+    #[clap(long, default_value_t = 12)]
+    depth: i32,
+    /// Drop lines played in fewer games
+    #[clap(short, long, default_value_t = 2)]
+    min_games: i64,
+  },
+  /// List where each of a player's games left the positions known from earlier games
+  Novelties {
+    #[clap(short, long)]
+    player: String,
+    /// Positions reached by fewer earlier games count as novelties
+    #[clap(short, long, default_value_t = 1)]
+    min_games: i64,
+  },
+  /// Look up every position of an EPD or FEN file, one per line
+  Positions {
+    #[clap(short, long)]
+    input: String,
+    #[clap(short, long, value_enum, default_value_t = OutputFormat::Csv)]
+    format: OutputFormat,
+  },
+  /// List the games that reached a material signature, like `KRPvKR`, with either color
+  Material {
+    signature: String,
+    /// Minimum number of plies spent with that material
+    #[clap(short, long, default_value_t = 1)]
+    min_plies: i64,
   }
 }
 
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum PlayerColor {
+  White,
+  Black,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum OutputFormat {
+  Csv,
+  Json,
+}
+
+/// Mirror of `RelatedOrder` for clap.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum RelatedOrderArg {
+  /// Highest rated first
+  Elo,
+  /// Most recent first
+  Date,
+}
+
+impl From<RelatedOrderArg> for RelatedOrder {
+  fn from(order: RelatedOrderArg) -> Self {
+    match order {
+      RelatedOrderArg::Elo => RelatedOrder::Elo,
+      RelatedOrderArg::Date => RelatedOrder::Date,
+    }
+  }
+}
+
+/// Mirror of `ThemeName` for clap.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum ThemeArg {
+  /// Magenta and yellow
+  Classic,
+  /// Brown, needs true color
+  Wood,
+  /// For light backgrounds
+  Light,
+  /// For dark backgrounds, needs true color
+  Dark,
+  /// Basic colors only
+  Contrast,
+}
+
+impl From<ThemeArg> for ThemeName {
+  fn from(theme: ThemeArg) -> Self {
+    match theme {
+      ThemeArg::Classic => ThemeName::Classic,
+      ThemeArg::Wood => ThemeName::Wood,
+      ThemeArg::Light => ThemeName::Light,
+      ThemeArg::Dark => ThemeName::Dark,
+      ThemeArg::Contrast => ThemeName::Contrast,
+    }
+  }
+}
+
+/// Mirror of `PieceSet` for clap.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum PiecesArg {
+  /// Filled glyphs
+  Filled,
+  /// Outlined glyphs for white
+  Outlined,
+  /// Letters
+  Ascii,
+  /// Figurine notation
+  Figurine,
+}
+
+impl From<PiecesArg> for PieceSet {
+  fn from(pieces: PiecesArg) -> Self {
+    match pieces {
+      PiecesArg::Filled => PieceSet::Filled,
+      PiecesArg::Outlined => PieceSet::Outlined,
+      PiecesArg::Ascii => PieceSet::Ascii,
+      PiecesArg::Figurine => PieceSet::Figurine,
+    }
+  }
+}
+
+#[derive(serde::Serialize)]
+struct PositionRow<'a> {
+  epd: &'a str,
+  #[serde(flatten)]
+  stats: &'a PositionStats,
+}
+
+fn print_signature_games(games: &[SignatureReach]) {
+  println!("{} games reach this material", games.len());
+  for reach in games {
+    let entry = match &reach.entry_move {
+      Some(entry_move) => format!("entered after {}", entry_move.numbered_san()),
+      None => "from the start".to_string(),
+    };
+    println!("[{}] {} ({}) vs {} ({}) - {} @ {}: {entry}, {} plies",
+      reach.game.id.id, reach.game.white, elo(reach.game.white_elo), reach.game.black, elo(reach.game.black_elo),
+      reach.game.event, reach.game.datetime, reach.plies);
+  }
+}
+
+fn elo(elo: Option<i32>) -> String {
+  elo.map_or("?".to_string(), |elo| elo.to_string())
+}
+
+fn print_positions_stats(positions: &[(String, PositionStats)], format: OutputFormat) {
+  match format {
+    OutputFormat::Csv => {
+      println!("epd,board_hash,occurrences,games,white_wins,draws,black_wins");
+      for (epd, stats) in positions {
+        println!("{},{},{},{},{},{},{}",
+          epd, stats.board_hash, stats.occurrences, stats.games, stats.white_wins, stats.draws, stats.black_wins);
+      }
+    },
+    OutputFormat::Json => {
+      let rows: Vec<PositionRow> = positions.iter()
+        .map(|(epd, stats)| PositionRow { epd, stats })
+        .collect();
+      println!("{}", serde_json::to_string_pretty(&rows).expect("Could not serialize positions"));
+    },
+  }
+}
+
+fn print_position_games(title: &str, games: &[(Move, Game)]) {
+  let mut next_moves: Vec<(String, usize)> = Vec::new();
+  for (mvmt, _) in games {
+    let san = format!("{}", mvmt.san_plus.0);
+    match next_moves.iter_mut().find(|(played, _)| *played == san) {
+      Some((_, count)) => *count += 1,
+      None => next_moves.push((san, 1)),
+    }
+  }
+  next_moves.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+  println!("{} games reach this {title}", games.len());
+  for (san, count) in next_moves {
+    println!("  {san}: {count}");
+  }
+  for (mvmt, game) in games {
+    println!("[{}] {} - {} ({}) vs {} ({}) - {} @ {}",
+      game.id.id, mvmt.numbered_san(), game.white, elo(game.white_elo), game.black, elo(game.black_elo), game.event, game.datetime);
+  }
+}
+
+/// Runs `query` on a fresh connection to the database, blocking until it is done.
+fn with_connection<T, F, Fut>(db_url: &str, query: F) -> Result<T, InsertionError>
+where
+  F: FnOnce(PgConnection) -> Fut,
+  Fut: Future<Output = Result<T, InsertionError>>,
+{
+  let runtime = tokio::runtime::Builder::new_current_thread()
+    .enable_all()
+    .build()?;
+  runtime.block_on(async {
+    let conn = PgConnection::connect(db_url).await?;
+    query(conn).await
+  })
+}
+
 fn main() -> Result<(), InsertionError> {
   let args = NixChessArgs::parse();
   
@@ -39,7 +272,13 @@ fn main() -> Result<(), InsertionError> {
       }));
       cursive::logger::init(); // enables debugging console.
       
-      cli_entrypoint(db_url);
+      let related = RelatedGamesOptions {
+        min_ply: args.related_from_ply,
+        limit: args.related_limit,
+        order: args.related_order.into(),
+      };
+      let board_theme = BoardTheme { colors: args.theme.into(), pieces: args.pieces.into() };
+      cli_entrypoint(db_url, related, board_theme, args.large_board, args.novelty_min_games);
       
       Ok(())
     },
@@ -58,5 +297,84 @@ fn main() -> Result<(), InsertionError> {
         Ok::<(), InsertionError>(())
       })
     },
+    Some(Command::Position { fen }) => {
+      let board_hash = position_hash(&position_from_fen(&fen)?);
+      let games = with_connection(&db_url, |mut conn| async move {
+        movement_and_games_from_position(&mut conn, board_hash).await
+      })?;
+      print_position_games("position", &games);
+      Ok(())
+    },
+    Some(Command::Pawns { fen }) => {
+      let pawn_hash = pawn_structure_hash(&position_from_fen(&fen)?);
+      let games = with_connection(&db_url, |mut conn| async move {
+        games_from_pawn_structure(&mut conn, pawn_hash, None).await
+      })?;
+      print_position_games("pawn structure", &games);
+      Ok(())
+    },
+    Some(Command::Pattern { pattern }) => {
+      let pattern = PiecePattern::parse(&pattern)?;
+      let games = with_connection(&db_url, |mut conn| async move {
+        games_from_pattern(&mut conn, &pattern).await
+      })?;
+      print_position_games("pattern", &games);
+      Ok(())
+    },
+    Some(Command::Repertoire { player, color, depth, min_games }) => {
+      let as_white = matches!(color, PlayerColor::White);
+      let player_name = &player;
+      let lines = with_connection(&db_url, |mut conn| async move {
+        opening_lines_from_player(&mut conn, player_name, as_white, depth).await
+      })?;
+      print!("{}", Repertoire::build(player, as_white, lines, min_games).to_pgn());
+      Ok(())
+    },
+    Some(Command::Novelties { player, min_games }) => {
+      let player_name = &player;
+      let mut novelties = with_connection(&db_url, |mut conn| async move {
+        novelties_from_player(&mut conn, player_name, min_games).await
+      })?;
+      novelties.sort_by_key(|(game, _)| game.datetime);
+      println!("{} games by {player} with a novelty", novelties.len());
+      for (game, novelty) in novelties {
+        let author = if novelty.game_round % 2 == 1 { &game.white } else { &game.black };
+        println!("[{}] {} vs {} - {} @ {}: {} by {author}",
+          game.id.id, game.white, game.black, game.event, game.datetime, novelty.numbered_san());
+      }
+      Ok(())
+    },
+    Some(Command::Positions { input, format }) => {
+      let mut epds = Vec::new();
+      let mut board_hashes = Vec::new();
+      for (line_number, line) in std::fs::read_to_string(input)?.lines().enumerate() {
+        if line.trim().is_empty() {
+          continue;
+        }
+        let position = position_from_epd(line)
+          .map_err(|err| InsertionError::InvalidPosition(format!("line {}: {err:?}", line_number + 1)))?;
+        board_hashes.push(position_hash(&position));
+        epds.push(Epd::from_position(position, EnPassantMode::Legal).to_string());
+      }
+      let hashes = &board_hashes;
+      let stats = with_connection(&db_url, |mut conn| async move {
+        stats_from_positions(&mut conn, hashes).await
+      })?;
+      let stats: HashMap<i64, PositionStats> = stats.into_iter().map(|stats| (stats.board_hash, stats)).collect();
+      let positions: Vec<(String, PositionStats)> = epds.into_iter()
+        .zip(board_hashes)
+        .filter_map(|(epd, hash)| Some((epd, stats.get(&(hash.0 as i64))?.clone())))
+        .collect();
+      print_positions_stats(&positions, format);
+      Ok(())
+    },
+    Some(Command::Material { signature, min_plies }) => {
+      let signatures = material_signatures(&signature)?;
+      let games = with_connection(&db_url, |mut conn| async move {
+        games_from_material(&mut conn, &signatures, min_plies).await
+      })?;
+      print_signature_games(&games);
+      Ok(())
+    },
   }
 }
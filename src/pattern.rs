@@ -0,0 +1,165 @@
+use crate::db::InsertionError;
+use shakmaty::{Bitboard, Board, Color, File, Piece, Rank, Role, Square};
+
+/// A partial board, like `Nd5 pd6 !b@light`.
+///
+/// Each term is a piece letter (uppercase for white, lowercase for black),
+/// optionally prefixed with `!` to forbid it, followed by where it stands:
+/// - `Nd5` or `N@d5`: on that square.
+/// - `P@e`, `r@7`: somewhere on that file or rank.
+/// - `B@light`, `b@dark`: somewhere on those squares.
+/// - `Q`: anywhere on the board.
+#[derive(Debug, Clone)]
+pub struct PiecePattern {
+  /// Pieces that must stand exactly on their squares.
+  pub required: Board,
+  /// At least one of the piece must be inside the region.
+  pub present: Vec<(Piece, Bitboard)>,
+  /// None of the piece may be inside the region.
+  pub absent: Vec<(Piece, Bitboard)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PatternCondition {
+  /// All squares of the mask are occupied by the piece.
+  All,
+  /// At least one square of the mask is occupied by the piece.
+  Any,
+  /// No square of the mask is occupied by the piece.
+  Absent,
+}
+
+fn parse_region(region: &str) -> Option<Bitboard> {
+  let mut chars = region.chars();
+  match (chars.next(), chars.next(), chars.next()) {
+    (None, _, _) | (Some('*'), None, _) => Some(Bitboard::FULL),
+    (Some(file), None, _) if File::from_char(file).is_some() => File::from_char(file).map(Bitboard::from_file),
+    (Some(rank), None, _) if Rank::from_char(rank).is_some() => Rank::from_char(rank).map(Bitboard::from_rank),
+    (Some(_), Some(_), None) => region.parse::<Square>().ok().map(Bitboard::from_square),
+    _ => match region {
+      "light" => Some(Bitboard::LIGHT_SQUARES),
+      "dark" => Some(Bitboard::DARK_SQUARES),
+      _ => None,
+    }
+  }
+}
+
+impl PiecePattern {
+  pub fn parse(pattern: &str) -> Result<Self, InsertionError> {
+    if pattern.trim().is_empty() {
+      // it would match every position.
+      return Err(InsertionError::InvalidPosition("empty pattern, expected pieces like Nd5 pd6".to_string()));
+    }
+    let mut parsed = PiecePattern { required: Board::empty(), present: Vec::new(), absent: Vec::new() };
+    for term in pattern.split_whitespace() {
+      let invalid = || InsertionError::InvalidPosition(format!("invalid pattern term: {term}"));
+      let (negated, rest) = match term.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, term),
+      };
+      let mut chars = rest.chars();
+      let piece = chars.next().and_then(Piece::from_char).ok_or_else(invalid)?;
+      let region = chars.as_str();
+      let region = region.strip_prefix('@').unwrap_or(region);
+      let mask = parse_region(region).ok_or_else(invalid)?;
+      match (negated, mask.single_square()) {
+        (true, _) => parsed.absent.push((piece, mask)),
+        (false, Some(square)) => {
+          if parsed.required.piece_at(square).map_or(false, |other| other != piece) {
+            return Err(invalid());
+          }
+          parsed.required.set_piece_at(square, piece)
+        },
+        (false, None) => parsed.present.push((piece, mask)),
+      }
+    }
+    Ok(parsed)
+  }
+
+  /// Every condition of the pattern, as a piece, a mask of squares, and how they must match.
+  pub fn conditions(&self) -> Vec<(Piece, Bitboard, PatternCondition)> {
+    let mut conditions = Vec::new();
+    for color in [Color::White, Color::Black] {
+      for role in Role::ALL {
+        let piece = role.of(color);
+        let mask = self.required.by_piece(piece);
+        if mask.any() {
+          conditions.push((piece, mask, PatternCondition::All));
+        }
+      }
+    }
+    conditions.extend(self.present.iter().map(|(piece, mask)| (*piece, *mask, PatternCondition::Any)));
+    conditions.extend(self.absent.iter().map(|(piece, mask)| (*piece, *mask, PatternCondition::Absent)));
+    conditions
+  }
+}
+
+/// Code of a piece on a square in the `piece_placement` index: `(color * 6 + role) * 64 + square`,
+/// white first and roles from pawn to king.
+pub fn placement_code(piece: Piece, square: Square) -> i16 {
+  let color = match piece.color {
+    Color::White => 0,
+    Color::Black => 6,
+  };
+  (color + piece.role as i16 - 1) * 64 + square as i16
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn parse(pattern: &str) -> Vec<(Piece, Bitboard, PatternCondition)> {
+    PiecePattern::parse(pattern).unwrap().conditions()
+  }
+
+  #[test]
+  fn parses_regions() {
+    assert_eq!(parse_region(""), Some(Bitboard::FULL));
+    assert_eq!(parse_region("*"), Some(Bitboard::FULL));
+    assert_eq!(parse_region("e"), Some(Bitboard::from_file(File::E)));
+    assert_eq!(parse_region("7"), Some(Bitboard::from_rank(Rank::Seventh)));
+    assert_eq!(parse_region("d5"), Some(Bitboard::from_square(Square::D5)));
+    assert_eq!(parse_region("light"), Some(Bitboard::LIGHT_SQUARES));
+    assert_eq!(parse_region("dark"), Some(Bitboard::DARK_SQUARES));
+    assert_eq!(parse_region("i"), None);
+    assert_eq!(parse_region("d9"), None);
+    assert_eq!(parse_region("grey"), None);
+  }
+
+  #[test]
+  fn sorts_terms_into_conditions() {
+    let white_knight = Piece::from_char('N').unwrap();
+    let black_pawn = Piece::from_char('p').unwrap();
+    let black_bishop = Piece::from_char('b').unwrap();
+    let white_queen = Piece::from_char('Q').unwrap();
+    assert_eq!(parse("Nd5 N@f3 pd6 !b@light Q"), [
+      (white_knight, Bitboard::from_square(Square::D5) | Bitboard::from_square(Square::F3), PatternCondition::All),
+      (black_pawn, Bitboard::from_square(Square::D6), PatternCondition::All),
+      (white_queen, Bitboard::FULL, PatternCondition::Any),
+      (black_bishop, Bitboard::LIGHT_SQUARES, PatternCondition::Absent),
+    ]);
+    assert_eq!(parse("!Pd4"), [(Piece::from_char('P').unwrap(), Bitboard::from_square(Square::D4), PatternCondition::Absent)]);
+  }
+
+  #[test]
+  fn rejects_invalid_patterns() {
+    for pattern in ["", "  ", "Xd5", "Nd9", "N@grey", "Nd5 bd5", "!"] {
+      assert!(PiecePattern::parse(pattern).is_err(), "{pattern:?}");
+    }
+    assert!(PiecePattern::parse("Nd5 Nd5").is_ok());
+  }
+
+  #[test]
+  fn placement_codes_match_the_sql_index() {
+    // piece_placement in upgrade.sql: white then black, roles from pawn to king, a1 being square 0.
+    let code = |piece: char, square: Square| placement_code(Piece::from_char(piece).unwrap(), square);
+    assert_eq!(code('P', Square::A1), 0);
+    assert_eq!(code('N', Square::G1), 64 + 6);
+    assert_eq!(code('K', Square::H8), 5 * 64 + 63);
+    assert_eq!(code('p', Square::E7), 6 * 64 + 52);
+    assert_eq!(code('k', Square::E8), 11 * 64 + 60);
+    let sql = include_str!("../pkg/nixchess-S0CU7JK5C238T4Y6/upgrade.sql");
+    assert!(sql.contains("THEN 0 ELSE 6 END + role) * 64 + square"));
+    assert!(sql.contains("(0, pawns), (1, knights), (2, bishops), (3, rooks), (4, queens), (5, kings)"));
+  }
+}
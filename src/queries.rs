@@ -1,47 +1,59 @@
-use crate::db::{InsertionError, Game, Move, GameId, SAN};
-use sqlx::PgConnection;
-use shakmaty::{san::SanPlus, zobrist::Zobrist64};
-use futures_util::TryStreamExt;
+use crate::db::{InsertionError, Game, Move, GameId, SAN, PositionStats, SignatureReach, GameOrder, GameSortKey, GameCursor, GameListQuery, GameTags, PlayerProfile, ColorScore, HeadToHead, RelatedGame, RelatedGamesOptions, RelatedOrder};
+use crate::pattern::{PiecePattern, PatternCondition, placement_code};
+use sqlx::{postgres::PgRow, Connection, PgConnection, Postgres, QueryBuilder, Row};
+use shakmaty::{san::SanPlus, zobrist::Zobrist64, Piece, Color, Role};
 
-pub async fn games_from_player(db: &mut PgConnection, player: &str) -> Result<Vec<Game>, InsertionError> {
-  let games = sqlx::query!(
-    r#"SELECT id, event, datetime, black, white, white_elo, black_elo FROM Game WHERE black = ($1) OR white = ($1)"#,
-    player
-  ).fetch_all(db)
-    .await?
-    .into_iter()
-    .map(|row| Game {
-      id: GameId { id: row.id },
-      event: row.event,
-      datetime: row.datetime,
-      white: row.white,
-      black: row.black,
-      white_elo: row.white_elo,
-      black_elo: row.black_elo,
-    })
-    .collect();
-  Ok(games)
+/// `Game` out of a `query!` row selecting the columns of `Game`.
+macro_rules! game_from_row {
+  ($row:ident, $id:expr) => {
+    Game {
+      id: $id,
+      event: $row.event,
+      datetime: $row.datetime,
+      white: $row.white,
+      black: $row.black,
+      white_elo: $row.white_elo,
+      black_elo: $row.black_elo,
+      result: $row.result,
+      plies: $row.plies,
+    }
+  };
 }
 
+/// Same as `game_from_row!`, for the rows of queries built at runtime.
+fn game_from_pg_row(row: &PgRow, id: GameId) -> Game {
+  Game {
+    id,
+    event: row.get("event"),
+    datetime: row.get("datetime"),
+    white: row.get("white"),
+    black: row.get("black"),
+    white_elo: row.get("white_elo"),
+    black_elo: row.get("black_elo"),
+    result: row.get("result"),
+    plies: row.get("plies"),
+  }
+}
 
-pub async fn movements_from_game(db: &mut PgConnection, game_id: GameId) -> Result<Vec<Move>, InsertionError> {
+/// Moves of a game in order, with the clock left after each of them if its PGN recorded it.
+pub async fn movements_from_game(db: &mut PgConnection, game_id: GameId) -> Result<(Vec<Move>, Vec<Option<i32>>), InsertionError> {
   let row = sqlx::query!(
-    r#"SELECT game_round, game_id, san_plus, board_hash FROM Move WHERE game_id = ($1) ORDER BY game_round"#,
+    r#"SELECT game_round, game_id, san_plus, board_hash, clock FROM Move WHERE game_id = ($1) ORDER BY game_round"#,
     game_id.id
   ).fetch_all(db)
     .await?;
-  let moves = row.into_iter().map(|row| Move {
+  let moves = row.into_iter().map(|row| (Move {
     board: Zobrist64(row.board_hash as u64),
     san_plus: SAN(SanPlus::from_ascii(row.san_plus.as_bytes()).unwrap()),
     game_id: GameId { id: row.game_id },
     game_round: row.game_round,
-  }).collect();
+  }, row.clock)).unzip();
   Ok(moves)
 }
 
 pub async fn movement_and_games_from_position(db: &mut PgConnection, board_hash: Zobrist64) -> Result<Vec<(Move, Game)>, InsertionError> {
   let row = sqlx::query!(
-    r#"SELECT game_round, game_id, san_plus, board_hash, white, black, event, datetime, white_elo, black_elo FROM (Move INNER JOIN Game ON game_id = id) WHERE board_hash = ($1)"#,
+    r#"SELECT game_round, game_id, san_plus, board_hash, white, black, event, datetime, white_elo, black_elo, result, plies FROM (Move INNER JOIN Game ON game_id = id) WHERE board_hash = ($1)"#,
     board_hash.0 as i64
   ).fetch_all(db)
     .await?;
@@ -52,91 +64,511 @@ pub async fn movement_and_games_from_position(db: &mut PgConnection, board_hash:
       san_plus: SAN(SanPlus::from_ascii(row.san_plus.as_bytes()).unwrap()),
       game_id: game_id.clone(),
       game_round: row.game_round,
-    }, Game {
-      id: game_id,
-      event: row.event,
-      datetime: row.datetime,
-      white: row.white,
-      black: row.black,
-      white_elo: row.white_elo,
-      black_elo: row.black_elo,
-    })
+    }, game_from_row!(row, game_id))
   }).collect();
   Ok(moves)
 }
 
 pub async fn game_from_move(db: &mut PgConnection, movement: Move) -> Result<Game, InsertionError> {
   let row = sqlx::query!(
-    r#"SELECT id, white, black, event, datetime, white_elo, black_elo from Game INNER JOIN move ON id = game_id WHERE id = ($1)"#,
+    r#"SELECT id, white, black, event, datetime, white_elo, black_elo, result, plies from Game INNER JOIN move ON id = game_id WHERE id = ($1)"#,
     movement.game_id.id
   )
     .fetch_one(db)
     .await?;
-  let game = Game {
-    id: GameId { id: row.id },
-    event: row.event,
-    datetime: row.datetime,
-    white: row.white,
-    black: row.black,
-    white_elo: row.white_elo,
-    black_elo: row.black_elo,
-  };
-  Ok(game)
+  Ok(game_from_row!(row, GameId { id: row.id }))
 }
 
 pub async fn game_from_id(db: &mut PgConnection, game_id: i32) -> Result<Game, InsertionError> {
   let row = sqlx::query!(
-    r#"SELECT id, white, black, event, datetime, white_elo, black_elo from Game WHERE id = ($1)"#,
+    r#"SELECT id, white, black, event, datetime, white_elo, black_elo, result, plies from Game WHERE id = ($1)"#,
     game_id
   )
     .fetch_one(db)
     .await?;
-  let game = Game {
-    id: GameId { id: row.id },
-    event: row.event,
-    datetime: row.datetime,
-    white: row.white,
-    black: row.black,
-    white_elo: row.white_elo,
-    black_elo: row.black_elo 
-  };
-  Ok(game)
-}
-
-pub async fn related_games_from_game(conn: &mut PgConnection, game_id: i32) -> Result<Vec<Vec<(Move, Game)>>, InsertionError> {
-  let mut query = sqlx::query!(
-    r#"WITH game_moves as (
-        SELECT game_round, board_hash FROM Move WHERE game_id = ($1) AND game_round > 6
-    )
-       SELECT Related.game_round, Related.board_hash, Related.game_id, Related.san_plus, black, white, datetime, id, event, white_elo, black_elo
-       FROM (Move as Related INNER JOIN game_moves ON (Related.board_hash = game_moves.board_hash) INNER JOIN Game ON Related.game_id = id)
-       WHERE Related.game_id != ($1)
-    "#, game_id
-  ).fetch(conn);
-  let mut ret = Vec::new();
-  while let Ok(Some(row)) = query.try_next().await {
-    let game = Game {
-      id: GameId { id: game_id },
-      event: row.event,
-      datetime: row.datetime,
-      white: row.white,
-      black: row.black,
-      white_elo: row.white_elo,
-      black_elo: row.black_elo,
-    };
-    let mvmt = Move {
-      board: Zobrist64(row.game_round as u64),
+  Ok(game_from_row!(row, GameId { id: row.id }))
+}
+
+/// Games reaching each position of a game, indexed by the position's move index in that game.
+pub async fn related_games_from_game(conn: &mut PgConnection, game_id: i32, options: &RelatedGamesOptions) -> Result<Vec<Vec<RelatedGame>>, InsertionError> {
+  let rows = sqlx::query!(
+    r#"WITH game_moves AS (
+        SELECT game_round, board_hash FROM Move WHERE game_id = ($1) AND game_round > ($2)
+      ), reached AS (
+        SELECT DISTINCT ON (game_moves.game_round, Related.game_id)
+          game_moves.game_round AS position_round, Related.game_round, Related.board_hash, Related.game_id, Related.san_plus
+        FROM Move AS Related INNER JOIN game_moves ON Related.board_hash = game_moves.board_hash
+        WHERE Related.game_id != ($1)
+        ORDER BY game_moves.game_round, Related.game_id, Related.game_round
+      ), ranked AS (
+        SELECT reached.*, ROW_NUMBER() OVER (
+          PARTITION BY position_round
+          ORDER BY CASE WHEN ($4) THEN (COALESCE(white_elo, black_elo) + COALESCE(black_elo, white_elo)) / 2 END DESC NULLS LAST,
+            datetime DESC, id
+        ) AS rank
+        FROM reached INNER JOIN Game ON reached.game_id = id
+      )
+      SELECT position_round AS "position_round!", ranked.game_round AS "game_round!", ranked.board_hash AS "board_hash!",
+        ranked.san_plus AS "san_plus!", black, white, datetime, id, event, white_elo, black_elo, result, plies,
+        ARRAY(
+          SELECT san_plus FROM Move AS Earlier
+          WHERE Earlier.game_id = id AND Earlier.game_round < ranked.game_round
+          ORDER BY Earlier.game_round
+        ) AS "path!"
+      FROM ranked INNER JOIN Game ON ranked.game_id = id
+      WHERE rank <= ($3)
+      ORDER BY position_round, rank
+    "#, game_id, options.min_ply, options.limit, options.order == RelatedOrder::Elo
+  ).fetch_all(conn).await?;
+  let mut ret: Vec<Vec<RelatedGame>> = Vec::new();
+  for row in rows {
+    let game = game_from_row!(row, GameId { id: row.id });
+    let next_move = Move {
+      board: Zobrist64(row.board_hash as u64),
       san_plus: SAN(SanPlus::from_ascii(row.san_plus.as_bytes()).unwrap()),
-      game_id: GameId { id: row.game_id },
+      game_id: GameId { id: row.id },
       game_round: row.game_round,
     };
-    let move_idx = (row.game_round - 1) as usize;
-    ret.resize(move_idx + 1, Vec::new());
-    let vec = ret.get_mut(move_idx).expect("Vector should always have an element");
-    vec.push((mvmt, game));
+    let path = row.path.iter()
+      .map(|san| SAN(SanPlus::from_ascii(san.as_bytes()).unwrap()))
+      .collect();
+    let move_idx = (row.position_round - 1) as usize;
+    if ret.len() <= move_idx {
+      ret.resize(move_idx + 1, Vec::new());
+    }
+    ret[move_idx].push(RelatedGame { game, next_move, path });
   }
-  
   Ok(ret)
 }
 
 
+
+pub async fn stats_from_positions(db: &mut PgConnection, board_hashes: &[Zobrist64]) -> Result<Vec<PositionStats>, InsertionError> {
+  let hashes: Vec<i64> = board_hashes.iter().map(|hash| hash.0 as i64).collect();
+  let stats = sqlx::query_as!(
+    PositionStats,
+    r#"SELECT positions.board_hash AS "board_hash!",
+         COUNT(Move.game_id) AS "occurrences!",
+         COUNT(DISTINCT Move.game_id) AS "games!",
+         COUNT(DISTINCT Move.game_id) FILTER (WHERE Game.result = '1-0') AS "white_wins!",
+         COUNT(DISTINCT Move.game_id) FILTER (WHERE Game.result = '1/2-1/2') AS "draws!",
+         COUNT(DISTINCT Move.game_id) FILTER (WHERE Game.result = '0-1') AS "black_wins!"
+       FROM UNNEST($1::bigint[]) AS positions(board_hash)
+       LEFT JOIN Move ON Move.board_hash = positions.board_hash
+       LEFT JOIN Game ON Move.game_id = Game.id
+       GROUP BY positions.board_hash"#,
+    &hashes
+  ).fetch_all(db)
+    .await?;
+  Ok(stats)
+}
+
+pub async fn games_from_material(db: &mut PgConnection, signatures: &[String], min_plies: i64) -> Result<Vec<SignatureReach>, InsertionError> {
+  let rows = sqlx::query!(
+    r#"WITH reached AS (
+         SELECT game_id, MIN(game_round) AS first_round, COUNT(*) AS signature_plies
+         FROM Move WHERE material = ANY($1::text[])
+         GROUP BY game_id
+         HAVING COUNT(*) >= ($2)
+       )
+       SELECT id, white, black, event, datetime, white_elo, black_elo, result, Game.plies,
+         reached.first_round AS "first_round!", reached.signature_plies AS "signature_plies!",
+         Entry.game_round AS "entry_round?", Entry.san_plus AS "entry_san?", Entry.board_hash AS "entry_hash?"
+       FROM reached
+       INNER JOIN Game ON id = reached.game_id
+       LEFT JOIN Move AS Entry ON Entry.game_id = reached.game_id AND Entry.game_round = reached.first_round - 1
+       ORDER BY datetime"#,
+    signatures,
+    min_plies
+  ).fetch_all(db)
+    .await?;
+  let games = rows.into_iter().map(|row| {
+    let game_id = GameId { id: row.id };
+    let entry_move = match (row.entry_round, row.entry_san, row.entry_hash) {
+      (Some(game_round), Some(san_plus), Some(board_hash)) => Some(Move {
+        board: Zobrist64(board_hash as u64),
+        san_plus: SAN(SanPlus::from_ascii(san_plus.as_bytes()).unwrap()),
+        game_id: game_id.clone(),
+        game_round,
+      }),
+      _ => None,
+    };
+    SignatureReach {
+      game: game_from_row!(row, game_id),
+      first_round: row.first_round,
+      plies: row.signature_plies,
+      entry_move,
+    }
+  }).collect();
+  Ok(games)
+}
+
+/// First move of every game reaching the pawn structure, one row per game.
+pub async fn games_from_pawn_structure(db: &mut PgConnection, pawn_hash: Zobrist64, except_game: Option<i32>) -> Result<Vec<(Move, Game)>, InsertionError> {
+  let row = sqlx::query!(
+    r#"SELECT DISTINCT ON (game_id) game_round, game_id, san_plus, board_hash, white, black, event, datetime, white_elo, black_elo, result, plies
+       FROM (Move INNER JOIN Game ON game_id = id)
+       WHERE pawn_hash = ($1) AND game_id IS DISTINCT FROM ($2)
+       ORDER BY game_id, game_round"#,
+    pawn_hash.0 as i64,
+    except_game
+  ).fetch_all(db)
+    .await?;
+  let moves = row.into_iter().map(|row| {
+    let game_id = GameId { id: row.game_id };
+    (Move {
+      board: Zobrist64(row.board_hash as u64),
+      san_plus: SAN(SanPlus::from_ascii(row.san_plus.as_bytes()).unwrap()),
+      game_id: game_id.clone(),
+      game_round: row.game_round,
+    }, game_from_row!(row, game_id))
+  }).collect();
+  Ok(moves)
+}
+
+fn bitboard_column(piece: Piece) -> (&'static str, &'static str) {
+  let color = match piece.color {
+    Color::White => "white_bb",
+    Color::Black => "black_bb",
+  };
+  let role = match piece.role {
+    Role::Pawn => "pawn_bb",
+    Role::Knight => "knight_bb",
+    Role::Bishop => "bishop_bb",
+    Role::Rook => "rook_bb",
+    Role::Queen => "queen_bb",
+    Role::King => "king_bb",
+  };
+  (color, role)
+}
+
+/// Same expression as the `move_piece_placement_idx` index, so that placed pieces are looked up in it.
+const PIECE_PLACEMENT: &str = "piece_placement(white_bb, black_bb, pawn_bb, knight_bb, bishop_bb, rook_bb, queen_bb, king_bb)";
+
+/// First move of every game reaching a position that matches the pattern, one row per game.
+pub async fn games_from_pattern(db: &mut PgConnection, pattern: &PiecePattern) -> Result<Vec<(Move, Game)>, InsertionError> {
+  let mut query = QueryBuilder::new(
+    r#"SELECT DISTINCT ON (game_id) game_round, game_id, san_plus, board_hash, white, black, event, datetime, white_elo, black_elo, result, plies
+       FROM (Move INNER JOIN Game ON game_id = id) WHERE white_bb IS NOT NULL"#
+  );
+  for (piece, mask, condition) in pattern.conditions() {
+    let codes: Vec<i16> = mask.into_iter().map(|square| placement_code(piece, square)).collect();
+    match condition {
+      PatternCondition::All => query.push(format_args!(" AND {PIECE_PLACEMENT} @> ")).push_bind(codes),
+      PatternCondition::Any => query.push(format_args!(" AND {PIECE_PLACEMENT} && ")).push_bind(codes),
+      PatternCondition::Absent => {
+        let (color, role) = bitboard_column(piece);
+        query.push(format_args!(" AND ({color} & {role} & ")).push_bind(mask.0 as i64).push(") = 0")
+      },
+    };
+  }
+  query.push(" ORDER BY game_id, game_round");
+  let row = query.build().fetch_all(db).await?;
+  let moves = row.into_iter().map(|row| {
+    let game_id = GameId { id: row.get("game_id") };
+    (Move {
+      board: Zobrist64(row.get::<i64, _>("board_hash") as u64),
+      san_plus: SAN(SanPlus::from_ascii(row.get::<&str, _>("san_plus").as_bytes()).unwrap()),
+      game_id: game_id.clone(),
+      game_round: row.get("game_round"),
+    }, game_from_pg_row(&row, game_id))
+  }).collect();
+  Ok(moves)
+}
+
+/// Players whose name starts with, or looks like, `search`, with how many games they played.
+pub async fn players_matching(db: &mut PgConnection, search: &str, limit: i64) -> Result<Vec<(String, i64)>, InsertionError> {
+  let prefix = format!("{}%", search.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"));
+  // the default 0.3 threshold misses most typos in short usernames, and SET LOCAL keeps the lower
+  // one from leaking to the other queries of this pooled connection.
+  let mut tx = db.begin().await?;
+  sqlx::query("SET LOCAL pg_trgm.similarity_threshold = 0.2").execute(&mut tx).await?;
+  let players = sqlx::query!(
+    r#"SELECT player_name, (SELECT COUNT(*) FROM Game WHERE white = player_name OR black = player_name) AS "games!"
+       FROM Player
+       WHERE player_name ILIKE ($1) OR player_name % ($2)
+       ORDER BY player_name ILIKE ($1) DESC, similarity(player_name, ($2)) DESC, player_name
+       LIMIT ($3)"#,
+    prefix,
+    search,
+    limit
+  ).fetch_all(&mut tx)
+    .await?
+    .into_iter()
+    .map(|row| (row.player_name, row.games))
+    .collect();
+  tx.commit().await?;
+  Ok(players)
+}
+
+fn push_game_sort_key(query: &mut QueryBuilder<Postgres>, order: GameOrder, player: &str) {
+  match order {
+    GameOrder::Date => { query.push("datetime"); },
+    GameOrder::Elo => {
+      query.push("COALESCE(CASE WHEN white = ").push_bind(player.to_string()).push(" THEN black_elo ELSE white_elo END, 0)");
+    },
+    GameOrder::Opponent => {
+      query.push("(CASE WHEN white = ").push_bind(player.to_string()).push(" THEN black ELSE white END)");
+    },
+    GameOrder::Result => {
+      query.push("(CASE WHEN result = '1/2-1/2' THEN 1 WHEN result = (CASE WHEN white = ")
+        .push_bind(player.to_string())
+        .push(" THEN '1-0' ELSE '0-1' END) THEN 2 WHEN result IN ('1-0', '0-1') THEN 0 ELSE -1 END)");
+    },
+    GameOrder::Length => { query.push("COALESCE(plies, 0)"); },
+  }
+}
+
+fn push_game_filter(query: &mut QueryBuilder<Postgres>, player: &str, filter: &str, opponent: Option<&str>) {
+  if let Some(opponent) = opponent {
+    query.push(" AND (white = ").push_bind(opponent.to_string())
+      .push(" OR black = ").push_bind(opponent.to_string()).push(")");
+  }
+  if filter.is_empty() {
+    return;
+  }
+  let filter = format!("%{}%", filter.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"));
+  query.push(" AND ((CASE WHEN white = ").push_bind(player.to_string())
+    .push(" THEN black ELSE white END) ILIKE ").push_bind(filter.clone())
+    .push(" OR event ILIKE ").push_bind(filter)
+    .push(")");
+}
+
+/// One page of a player's games, starting after `after`. Each game comes with the cursor of the next page.
+pub async fn games_from_player_page(db: &mut PgConnection, player: &str, list: &GameListQuery, after: Option<&GameCursor>, limit: i64) -> Result<Vec<(Game, GameCursor)>, InsertionError> {
+  let mut query = QueryBuilder::new("SELECT id, event, datetime, black, white, white_elo, black_elo, result, plies, ");
+  push_game_sort_key(&mut query, list.order, player);
+  query.push(" AS sort_key FROM Game WHERE (white = ").push_bind(player.to_string())
+    .push(" OR black = ").push_bind(player.to_string()).push(")");
+  push_game_filter(&mut query, player, &list.filter, list.opponent.as_deref());
+  if let Some(cursor) = after {
+    query.push(" AND (");
+    push_game_sort_key(&mut query, list.order, player);
+    query.push(", id)").push(if list.descending { " < (" } else { " > (" });
+    match &cursor.key {
+      GameSortKey::Date(datetime) => query.push_bind(*datetime),
+      GameSortKey::Number(number) => query.push_bind(*number),
+      GameSortKey::Text(text) => query.push_bind(text.clone()),
+    };
+    query.push(", ").push_bind(cursor.id).push(")");
+  }
+  let direction = if list.descending { "DESC" } else { "ASC" };
+  query.push(format_args!(" ORDER BY sort_key {direction}, id {direction} LIMIT ")).push_bind(limit);
+  let rows = query.build().fetch_all(db).await?;
+  let games = rows.into_iter().map(|row| {
+    let key = match list.order {
+      GameOrder::Date => GameSortKey::Date(row.get("sort_key")),
+      GameOrder::Elo | GameOrder::Result | GameOrder::Length => GameSortKey::Number(row.get("sort_key")),
+      GameOrder::Opponent => GameSortKey::Text(row.get("sort_key")),
+    };
+    let id: i32 = row.get("id");
+    (game_from_pg_row(&row, GameId { id }), GameCursor { key, id })
+  }).collect();
+  Ok(games)
+}
+
+pub async fn games_count_from_player(db: &mut PgConnection, player: &str, filter: &str, opponent: Option<&str>) -> Result<i64, InsertionError> {
+  let mut query = QueryBuilder::new("SELECT COUNT(*) FROM Game WHERE (white = ");
+  query.push_bind(player.to_string()).push(" OR black = ").push_bind(player.to_string()).push(")");
+  push_game_filter(&mut query, player, filter, opponent);
+  let count = query.build().fetch_one(db).await?.get(0);
+  Ok(count)
+}
+
+pub async fn player_profile(db: &mut PgConnection, player: &str) -> Result<PlayerProfile, InsertionError> {
+  let mut profile = PlayerProfile::default();
+  let scores = sqlx::query!(
+    r#"SELECT white = ($1) AS "as_white!", COUNT(*) AS "games!",
+         COUNT(*) FILTER (WHERE result = (CASE WHEN white = ($1) THEN '1-0' ELSE '0-1' END)) AS "wins!",
+         COUNT(*) FILTER (WHERE result = '1/2-1/2') AS "draws!",
+         COUNT(*) FILTER (WHERE result = (CASE WHEN white = ($1) THEN '0-1' ELSE '1-0' END)) AS "losses!"
+       FROM Game WHERE white = ($1) OR black = ($1)
+       GROUP BY white = ($1)"#,
+    player
+  ).fetch_all(&mut *db)
+    .await?;
+  for row in scores {
+    let score = ColorScore { games: row.games, wins: row.wins, draws: row.draws, losses: row.losses };
+    profile.games += score.games;
+    if row.as_white {
+      profile.as_white = score;
+    } else {
+      profile.as_black = score;
+    }
+  }
+  profile.rating_history = sqlx::query!(
+    r#"SELECT date_trunc('month', datetime) AS "month!",
+         AVG(CASE WHEN white = ($1) THEN white_elo ELSE black_elo END)::int AS "rating!"
+       FROM Game WHERE (white = ($1) OR black = ($1))
+         AND (CASE WHEN white = ($1) THEN white_elo ELSE black_elo END) IS NOT NULL
+       GROUP BY 1 ORDER BY 1"#,
+    player
+  ).fetch_all(&mut *db)
+    .await?
+    .into_iter()
+    .map(|row| (row.month, row.rating))
+    .collect();
+  profile.opponents = sqlx::query!(
+    r#"SELECT (CASE WHEN white = ($1) THEN black ELSE white END) AS "opponent!", COUNT(*) AS "games!"
+       FROM Game WHERE white = ($1) OR black = ($1)
+       GROUP BY 1 ORDER BY 2 DESC, 1 LIMIT 5"#,
+    player
+  ).fetch_all(&mut *db)
+    .await?
+    .into_iter()
+    .map(|row| (row.opponent, row.games))
+    .collect();
+  let openings = sqlx::query!(
+    r#"SELECT white = ($1) AS "as_white!", CONCAT_WS(' ', eco, opening) AS "opening!", COUNT(*) AS "games!"
+       FROM Game WHERE (white = ($1) OR black = ($1)) AND (eco IS NOT NULL OR opening IS NOT NULL)
+       GROUP BY 1, 2 ORDER BY 3 DESC, 2"#,
+    player
+  ).fetch_all(&mut *db)
+    .await?;
+  for row in openings {
+    let openings = if row.as_white { &mut profile.white_openings } else { &mut profile.black_openings };
+    if openings.len() < 5 {
+      openings.push((row.opening, row.games));
+    }
+  }
+  let length = sqlx::query!(
+    r#"SELECT AVG(plies)::float8 AS average, percentile_cont(0.5) WITHIN GROUP (ORDER BY plies) AS median
+       FROM Game WHERE white = ($1) OR black = ($1)"#,
+    player
+  ).fetch_one(&mut *db)
+    .await?;
+  profile.average_plies = length.average;
+  profile.median_plies = length.median;
+  Ok(profile)
+}
+
+/// Result and first `depth` moves of every game the player played with that color.
+pub async fn opening_lines_from_player(db: &mut PgConnection, player: &str, as_white: bool, depth: i32) -> Result<Vec<(Option<String>, Vec<SAN>)>, InsertionError> {
+  let lines = sqlx::query!(
+    r#"SELECT result, ARRAY_AGG(san_plus ORDER BY game_round) AS "moves!"
+       FROM Game INNER JOIN Move ON game_id = id
+       WHERE (CASE WHEN ($2) THEN white ELSE black END) = ($1) AND game_round <= ($3)
+       GROUP BY id"#,
+    player,
+    as_white,
+    depth
+  ).fetch_all(db)
+    .await?
+    .into_iter()
+    .map(|row| {
+      let moves = row.moves.iter()
+        .map(|san_plus| SAN(SanPlus::from_ascii(san_plus.as_bytes()).unwrap()))
+        .collect();
+      (row.result, moves)
+    })
+    .collect();
+  Ok(lines)
+}
+
+pub async fn head_to_head(db: &mut PgConnection, player: &str, opponent: &str) -> Result<HeadToHead, InsertionError> {
+  let mut head_to_head = HeadToHead::default();
+  let games = sqlx::query!(
+    r#"SELECT datetime, white = ($1) AS "as_white!", result, CONCAT_WS(' ', eco, opening) AS "opening!"
+       FROM Game WHERE (white = ($1) AND black = ($2)) OR (white = ($2) AND black = ($1))
+       ORDER BY datetime"#,
+    player,
+    opponent
+  ).fetch_all(db)
+    .await?;
+  for row in games {
+    let outcome = match (row.result.as_deref(), row.as_white) {
+      (Some("1/2-1/2"), _) => 'D',
+      (Some("1-0"), true) | (Some("0-1"), false) => 'W',
+      (Some("1-0"), false) | (Some("0-1"), true) => 'L',
+      _ => '?',
+    };
+    let score = if row.as_white { &mut head_to_head.as_white } else { &mut head_to_head.as_black };
+    score.games += 1;
+    match outcome {
+      'W' => score.wins += 1,
+      'D' => score.draws += 1,
+      'L' => score.losses += 1,
+      _ => {},
+    }
+    if !row.opening.is_empty() {
+      match head_to_head.openings.iter_mut().find(|(opening, _)| *opening == row.opening) {
+        Some((_, count)) => *count += 1,
+        None => head_to_head.openings.push((row.opening, 1)),
+      }
+    }
+    head_to_head.timeline.push((row.datetime, outcome));
+  }
+  head_to_head.openings.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+  Ok(head_to_head)
+}
+
+/// Game round of the first move leading to a position reached by fewer than `min_games` earlier games.
+pub async fn novelty_from_game(db: &mut PgConnection, game_id: i32, min_games: i64) -> Result<Option<i32>, InsertionError> {
+  // counting stops at `min_games` earlier moves, which is enough to know the position is known.
+  let row = sqlx::query!(
+    r#"SELECT Played.game_round
+       FROM Move AS Played INNER JOIN Game ON Game.id = Played.game_id
+       WHERE Played.game_id = ($1) AND Played.game_round > 1 AND (
+         SELECT COUNT(*) FROM (
+           SELECT 1 FROM Move AS Known INNER JOIN Game AS KnownGame ON KnownGame.id = Known.game_id
+           WHERE Known.board_hash = Played.board_hash AND KnownGame.datetime < Game.datetime
+           LIMIT ($2)
+         ) AS known_moves
+       ) < ($2)
+       ORDER BY Played.game_round
+       LIMIT 1"#,
+    game_id,
+    min_games
+  ).fetch_optional(db)
+    .await?;
+  // positions are stored before their move, so the one reached by a move is on the next row.
+  Ok(row.map(|row| row.game_round - 1))
+}
+
+/// Header tags of a game that the `Game` rows leave out.
+pub async fn tags_from_game(db: &mut PgConnection, game_id: i32) -> Result<GameTags, InsertionError> {
+  let tags = sqlx::query_as!(
+    GameTags,
+    r#"SELECT site, eco, opening, time_control, termination FROM Game WHERE id = ($1)"#,
+    game_id)
+    .fetch_one(db)
+    .await?;
+  Ok(tags)
+}
+
+/// First novelty of each of the player's games that has one, as in `novelty_from_game`.
+pub async fn novelties_from_player(db: &mut PgConnection, player: &str, min_games: i64) -> Result<Vec<(Game, Move)>, InsertionError> {
+  let rows = sqlx::query!(
+    r#"SELECT Game.id, white, black, event, datetime, white_elo, black_elo, result, plies,
+         Novelty.game_round, Novelty.san_plus, Novelty.board_hash
+       FROM Game
+       CROSS JOIN LATERAL (
+         SELECT Played.game_round FROM Move AS Played
+         WHERE Played.game_id = Game.id AND Played.game_round > 1 AND (
+           SELECT COUNT(*) FROM (
+             SELECT 1 FROM Move AS Known INNER JOIN Game AS KnownGame ON KnownGame.id = Known.game_id
+             WHERE Known.board_hash = Played.board_hash AND KnownGame.datetime < Game.datetime
+             LIMIT ($2)
+           ) AS known_moves
+         ) < ($2)
+         ORDER BY Played.game_round
+         LIMIT 1
+       ) AS First
+       INNER JOIN Move AS Novelty ON Novelty.game_id = Game.id AND Novelty.game_round = First.game_round - 1
+       WHERE white = ($1) OR black = ($1)
+       ORDER BY Game.id"#,
+    player,
+    min_games
+  ).fetch_all(db)
+    .await?;
+  let novelties = rows.into_iter().map(|row| {
+    let game_id = GameId { id: row.id };
+    (game_from_row!(row, game_id.clone()), Move {
+      board: Zobrist64(row.board_hash as u64),
+      san_plus: SAN(SanPlus::from_ascii(row.san_plus.as_bytes()).unwrap()),
+      game_id,
+      game_round: row.game_round,
+    })
+  }).collect();
+  Ok(novelties)
+}
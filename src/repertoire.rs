@@ -0,0 +1,272 @@
+use crate::db::{SAN, pgn_tag, position_hash};
+use shakmaty::{Chess, Position, zobrist::Zobrist64};
+use std::collections::{HashMap, HashSet};
+
+/// A position in a player's repertoire, the move reaching it, and the games that went through it
+/// by any move order.
+#[derive(Debug, Clone)]
+pub struct RepertoireNode {
+  pub san_plus: SAN,
+  /// Hash of the position the move reaches, shared by the nodes of its transpositions.
+  pub board_hash: Zobrist64,
+  /// Ply of the move, starting at 1 for white's first move.
+  pub game_round: i32,
+  pub games: i64,
+  /// Points scored by the player in those games (1 per win, 0.5 per draw).
+  pub points: f64,
+  /// Games with a decisive or drawn result, over which `points` is scored.
+  pub scored_games: i64,
+  pub children: Vec<RepertoireNode>,
+}
+
+/// Every position a player reached with one color, most played first.
+#[derive(Debug, Clone)]
+pub struct Repertoire {
+  pub player: String,
+  pub as_white: bool,
+  pub games: i64,
+  pub children: Vec<RepertoireNode>,
+}
+
+impl RepertoireNode {
+  pub fn score(&self) -> Option<f64> {
+    (self.scored_games > 0).then(|| self.points / self.scored_games as f64)
+  }
+
+  pub fn numbered_san(&self) -> String {
+    if self.game_round % 2 == 1 {
+      format!("{}. {}", (self.game_round + 1) / 2, self.san_plus.0)
+    } else {
+      format!("{}... {}", self.game_round / 2, self.san_plus.0)
+    }
+  }
+}
+
+fn player_points(result: Option<&str>, as_white: bool) -> Option<f64> {
+  match (result, as_white) {
+    (Some("1/2-1/2"), _) => Some(0.5),
+    (Some("1-0"), true) | (Some("0-1"), false) => Some(1.0),
+    (Some("1-0"), false) | (Some("0-1"), true) => Some(0.0),
+    _ => None,
+  }
+}
+
+/// Games, and the player's points, through a position.
+#[derive(Debug, Clone, Copy, Default)]
+struct PositionTally {
+  games: i64,
+  points: f64,
+  scored_games: i64,
+}
+
+/// Every position the player's games reached, and the moves played from each, keyed by position hash
+/// so that lines transposing into each other share their counts.
+#[derive(Debug, Default)]
+struct PositionGraph {
+  tallies: HashMap<Zobrist64, PositionTally>,
+  moves: HashMap<Zobrist64, Vec<(SAN, Zobrist64)>>,
+}
+
+impl PositionGraph {
+  fn add_game(&mut self, moves: &[SAN], points: Option<f64>) {
+    let mut position = Chess::default();
+    let mut from = position_hash(&position);
+    let mut reached = HashSet::new();
+    for san in moves {
+      let Ok(mov) = san.0.san.to_move(&position) else {
+        break;
+      };
+      position.play_unchecked(&mov);
+      let to = position_hash(&position);
+      let next_moves = self.moves.entry(from).or_default();
+      if !next_moves.iter().any(|(_, next)| *next == to) {
+        next_moves.push((san.clone(), to));
+      }
+      // a game repeating a position still counts once for it.
+      if reached.insert(to) {
+        let tally = self.tallies.entry(to).or_default();
+        tally.games += 1;
+        if let Some(points) = points {
+          tally.points += points;
+          tally.scored_games += 1;
+        }
+      }
+      from = to;
+    }
+  }
+
+  /// Moves played from `from` in at least `min_games` games, most played first, then best scoring,
+  /// skipping positions already on the line to stay a tree.
+  fn nodes_from(&self, from: Zobrist64, game_round: i32, min_games: i64, line: &mut Vec<Zobrist64>) -> Vec<RepertoireNode> {
+    let mut nodes = Vec::new();
+    for (san_plus, to) in self.moves.get(&from).into_iter().flatten() {
+      let tally = self.tallies[to];
+      if tally.games < min_games || line.contains(to) {
+        continue;
+      }
+      line.push(*to);
+      let children = self.nodes_from(*to, game_round + 1, min_games, line);
+      line.pop();
+      nodes.push(RepertoireNode {
+        san_plus: san_plus.clone(),
+        board_hash: *to,
+        game_round,
+        games: tally.games,
+        points: tally.points,
+        scored_games: tally.scored_games,
+        children,
+      });
+    }
+    nodes.sort_by(|a, b| {
+      b.games.cmp(&a.games)
+        .then_with(|| b.score().unwrap_or(-1.0).total_cmp(&a.score().unwrap_or(-1.0)))
+        .then_with(|| a.san_plus.0.to_string().cmp(&b.san_plus.0.to_string()))
+    });
+    nodes
+  }
+}
+
+impl Repertoire {
+  /// Builds the tree from each game's result and opening moves, dropping lines played in fewer than `min_games`.
+  pub fn build(player: String, as_white: bool, games: Vec<(Option<String>, Vec<SAN>)>, min_games: i64) -> Self {
+    let mut graph = PositionGraph::default();
+    let games_number = games.len() as i64;
+    for (result, moves) in games {
+      graph.add_game(&moves, player_points(result.as_deref(), as_white));
+    }
+    let start = position_hash(&Chess::default());
+    let children = graph.nodes_from(start, 1, min_games.max(1), &mut vec![start]);
+    Repertoire { player, as_white, games: games_number, children }
+  }
+
+  /// The tree as a single PGN game, the most played move of each position being the main line.
+  pub fn to_pgn(&self) -> String {
+    let (white, black) = if self.as_white { (self.player.as_str(), "?") } else { ("?", self.player.as_str()) };
+    let color = if self.as_white { "white" } else { "black" };
+    let mut pgn = [
+      pgn_tag("Event", &format!("Repertoire of {} as {color}", self.player)),
+      pgn_tag("White", white),
+      pgn_tag("Black", black),
+      pgn_tag("Result", "*"),
+    ].concat();
+    pgn.push('\n');
+    let mut moves = String::new();
+    write_variations(&self.children, &mut moves);
+    moves.push_str(" *");
+    pgn.push_str(moves.trim_start());
+    pgn.push('\n');
+    pgn
+  }
+}
+
+fn write_move(node: &RepertoireNode, pgn: &mut String) {
+  pgn.push_str(&node.numbered_san());
+  match node.score() {
+    Some(score) => pgn.push_str(&format!(" {{{} games, {:.0}%}}", node.games, score * 100.0)),
+    None => pgn.push_str(&format!(" {{{} games}}", node.games)),
+  }
+}
+
+fn write_variations(children: &[RepertoireNode], pgn: &mut String) {
+  let Some((main, alternatives)) = children.split_first() else {
+    return;
+  };
+  pgn.push(' ');
+  write_move(main, pgn);
+  for alternative in alternatives {
+    pgn.push_str(" (");
+    write_move(alternative, pgn);
+    write_variations(&alternative.children, pgn);
+    pgn.push(')');
+  }
+  write_variations(&main.children, pgn);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use shakmaty::san::SanPlus;
+
+  fn game(result: &str, moves: &str) -> (Option<String>, Vec<SAN>) {
+    let moves = moves.split_whitespace()
+      .map(|san| SAN(SanPlus::from_ascii(san.as_bytes()).unwrap()))
+      .collect();
+    (Some(result.to_string()), moves)
+  }
+
+  fn sans(nodes: &[RepertoireNode]) -> Vec<String> {
+    nodes.iter().map(|node| node.san_plus.0.to_string()).collect()
+  }
+
+  #[test]
+  fn transpositions_share_their_games() {
+    let games = vec![
+      game("1-0", "e4 e5 Nf3 Nc6"),
+      game("0-1", "Nf3 Nc6 e4 e5"),
+    ];
+    let repertoire = Repertoire::build("alice".to_string(), true, games, 1);
+    assert_eq!(repertoire.games, 2);
+    let e4_line = &repertoire.children[0];
+    let nf3_line = &repertoire.children[1];
+    let after_e4 = &e4_line.children[0].children[0].children[0];
+    let after_nf3 = &nf3_line.children[0].children[0].children[0];
+    assert_eq!(after_e4.board_hash, after_nf3.board_hash);
+    assert_eq!((after_e4.games, after_nf3.games), (2, 2));
+    assert_eq!(after_e4.score(), Some(0.5));
+  }
+
+  #[test]
+  fn ties_are_broken_by_score_then_san() {
+    let games = vec![
+      game("0-1", "d4"),
+      game("1-0", "e4"),
+      game("1/2-1/2", "c4"),
+      game("1/2-1/2", "b3"),
+      game("1-0", "Nf3"),
+      game("1-0", "Nf3"),
+    ];
+    let repertoire = Repertoire::build("alice".to_string(), true, games, 1);
+    assert_eq!(sans(&repertoire.children), ["Nf3", "e4", "b3", "c4", "d4"]);
+  }
+
+  #[test]
+  fn rare_lines_are_dropped() {
+    let games = vec![
+      game("1-0", "e4 e5"),
+      game("1-0", "e4 c5"),
+      game("1-0", "d4 d5"),
+    ];
+    let repertoire = Repertoire::build("alice".to_string(), false, games, 2);
+    assert_eq!(sans(&repertoire.children), ["e4"]);
+    assert!(repertoire.children[0].children.is_empty());
+    assert_eq!(repertoire.children[0].score(), Some(0.0));
+  }
+
+  #[test]
+  fn repeated_positions_count_once_and_end_the_line() {
+    let games = vec![game("1/2-1/2", "Nf3 Nf6 Ng1 Ng8 Nf3")];
+    let repertoire = Repertoire::build("alice".to_string(), true, games, 1);
+    // Ng8 goes back to the starting position, already on the line.
+    let ng1 = &repertoire.children[0].children[0].children[0];
+    assert_eq!(ng1.san_plus.0.to_string(), "Ng1");
+    assert!(ng1.children.is_empty());
+    assert_eq!(repertoire.children[0].games, 1);
+  }
+
+  #[test]
+  fn exports_variations_nested_under_their_move() {
+    let games = vec![
+      game("1-0", "e4 e5 Nf3"),
+      game("1-0", "e4 e5 Nf3"),
+      game("0-1", "e4 c5"),
+      game("*", "d4"),
+    ];
+    let repertoire = Repertoire::build("al \"the\" ice".to_string(), true, games, 1);
+    let pgn = repertoire.to_pgn();
+    assert!(pgn.starts_with("[Event \"Repertoire of al \\\"the\\\" ice as white\"]\n[White \"al \\\"the\\\" ice\"]\n"));
+    assert!(pgn.ends_with(concat!(
+      "\n\n1. e4 {3 games, 67%} (1. d4 {1 games}) 1... e5 {2 games, 100%} (1... c5 {1 games, 0%}) ",
+      "2. Nf3 {2 games, 100%} *\n",
+    )));
+  }
+}
@@ -0,0 +1,203 @@
+use cursive::theme::{BaseColor, Color};
+use shakmaty::{Color as PieceColor, Piece, Role};
+
+/// Colors of the squares, highlights and pieces of the board.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThemeColors {
+  pub light_square: Color,
+  pub dark_square: Color,
+  /// Square the last move left.
+  pub last_move_from: Color,
+  /// Square the last move reached.
+  pub last_move_to: Color,
+  /// Square under the keyboard cursor.
+  pub cursor: Color,
+  /// Square picked as the origin of a move.
+  pub selected: Color,
+  pub white_piece: Color,
+  pub black_piece: Color,
+}
+
+/// Built-in color themes, cycled through in the order they are declared.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ThemeName {
+  /// Magenta squares with yellow highlights.
+  Classic,
+  /// Brown squares, in true color.
+  Wood,
+  /// Pale squares with blue and black pieces, for light terminal backgrounds.
+  Light,
+  /// Slate squares, in true color, for dark terminal backgrounds.
+  Dark,
+  /// Only the basic terminal colors, as bright as they go.
+  Contrast,
+}
+
+const CLASSIC: ThemeColors = ThemeColors {
+  light_square: Color::Light(BaseColor::Magenta),
+  dark_square: Color::Dark(BaseColor::Magenta),
+  last_move_from: Color::Dark(BaseColor::Yellow),
+  last_move_to: Color::Light(BaseColor::Yellow),
+  cursor: Color::Light(BaseColor::Cyan),
+  selected: Color::Dark(BaseColor::Cyan),
+  white_piece: Color::Dark(BaseColor::White),
+  black_piece: Color::Dark(BaseColor::Black),
+};
+
+const WOOD: ThemeColors = ThemeColors {
+  light_square: Color::Rgb(240, 217, 181),
+  dark_square: Color::Rgb(181, 136, 99),
+  last_move_from: Color::Rgb(170, 162, 58),
+  last_move_to: Color::Rgb(205, 210, 106),
+  cursor: Color::Rgb(130, 180, 230),
+  selected: Color::Rgb(80, 130, 190),
+  white_piece: Color::Rgb(255, 255, 255),
+  black_piece: Color::Rgb(0, 0, 0),
+};
+
+const LIGHT: ThemeColors = ThemeColors {
+  light_square: Color::Light(BaseColor::White),
+  dark_square: Color::Dark(BaseColor::White),
+  last_move_from: Color::Dark(BaseColor::Yellow),
+  last_move_to: Color::Light(BaseColor::Yellow),
+  cursor: Color::Light(BaseColor::Cyan),
+  selected: Color::Dark(BaseColor::Cyan),
+  white_piece: Color::Dark(BaseColor::Blue),
+  black_piece: Color::Dark(BaseColor::Black),
+};
+
+const DARK: ThemeColors = ThemeColors {
+  light_square: Color::Rgb(125, 135, 150),
+  dark_square: Color::Rgb(80, 90, 105),
+  last_move_from: Color::Rgb(150, 140, 60),
+  last_move_to: Color::Rgb(190, 180, 90),
+  cursor: Color::Rgb(110, 170, 130),
+  selected: Color::Rgb(70, 130, 90),
+  white_piece: Color::Rgb(255, 255, 255),
+  black_piece: Color::Rgb(0, 0, 0),
+};
+
+const CONTRAST: ThemeColors = ThemeColors {
+  light_square: Color::Dark(BaseColor::Blue),
+  dark_square: Color::Dark(BaseColor::Black),
+  last_move_from: Color::Dark(BaseColor::Green),
+  last_move_to: Color::Light(BaseColor::Green),
+  cursor: Color::Light(BaseColor::Magenta),
+  selected: Color::Dark(BaseColor::Magenta),
+  white_piece: Color::Light(BaseColor::White),
+  black_piece: Color::Light(BaseColor::Red),
+};
+
+impl ThemeName {
+  pub const ALL: [ThemeName; 5] =
+    [ThemeName::Classic, ThemeName::Wood, ThemeName::Light, ThemeName::Dark, ThemeName::Contrast];
+
+  pub fn name(self) -> &'static str {
+    match self {
+      ThemeName::Classic => "classic",
+      ThemeName::Wood => "wood",
+      ThemeName::Light => "light",
+      ThemeName::Dark => "dark",
+      ThemeName::Contrast => "contrast",
+    }
+  }
+
+  pub fn colors(self) -> &'static ThemeColors {
+    match self {
+      ThemeName::Classic => &CLASSIC,
+      ThemeName::Wood => &WOOD,
+      ThemeName::Light => &LIGHT,
+      ThemeName::Dark => &DARK,
+      ThemeName::Contrast => &CONTRAST,
+    }
+  }
+
+  pub fn next(self) -> Self {
+    let index = Self::ALL.iter().position(|name| *name == self).unwrap_or(0);
+    Self::ALL[(index + 1) % Self::ALL.len()]
+  }
+}
+
+/// How pieces are drawn on the board.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PieceSet {
+  /// Filled glyphs for both sides, told apart by their color.
+  Filled,
+  /// Outlined glyphs for white and filled ones for black.
+  Outlined,
+  /// Letters, uppercase for white and lowercase for black, for terminals lacking chess glyphs.
+  Ascii,
+  /// Filled glyphs, with moves written in figurine notation, like `♘f3`.
+  Figurine,
+}
+
+impl PieceSet {
+  pub const ALL: [PieceSet; 4] = [PieceSet::Filled, PieceSet::Outlined, PieceSet::Ascii, PieceSet::Figurine];
+
+  pub fn name(self) -> &'static str {
+    match self {
+      PieceSet::Filled => "filled",
+      PieceSet::Outlined => "outlined",
+      PieceSet::Ascii => "ascii",
+      PieceSet::Figurine => "figurine",
+    }
+  }
+
+  pub fn next(self) -> Self {
+    let index = Self::ALL.iter().position(|set| *set == self).unwrap_or(0);
+    Self::ALL[(index + 1) % Self::ALL.len()]
+  }
+
+  pub fn piece_char(self, piece: Piece) -> char {
+    match (self, piece.color) {
+      (PieceSet::Ascii, _) => piece.char(),
+      (PieceSet::Outlined, PieceColor::White) => outlined_glyph(piece.role),
+      _ => filled_glyph(piece.role),
+    }
+  }
+
+  /// A move as written in the movement list, like `Nf3` or `♘f3`.
+  pub fn notation(self, san: &str) -> String {
+    if self != PieceSet::Figurine {
+      return san.to_string();
+    }
+    san.chars()
+      .map(|c| Role::from_char(c).filter(|_| c.is_ascii_uppercase()).map_or(c, outlined_glyph))
+      .collect()
+  }
+}
+
+fn filled_glyph(role: Role) -> char {
+  match role {
+    Role::King => '\u{265A}',
+    Role::Queen => '\u{265B}',
+    Role::Rook => '\u{265C}',
+    Role::Bishop => '\u{265D}',
+    Role::Knight => '\u{265E}',
+    Role::Pawn => '\u{265F}',
+  }
+}
+
+fn outlined_glyph(role: Role) -> char {
+  match role {
+    Role::King => '\u{2654}',
+    Role::Queen => '\u{2655}',
+    Role::Rook => '\u{2656}',
+    Role::Bishop => '\u{2657}',
+    Role::Knight => '\u{2658}',
+    Role::Pawn => '\u{2659}',
+  }
+}
+
+/// Colors and piece set the board is drawn with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoardTheme {
+  pub colors: ThemeName,
+  pub pieces: PieceSet,
+}
+
+impl Default for BoardTheme {
+  fn default() -> Self {
+    BoardTheme { colors: ThemeName::Classic, pieces: PieceSet::Filled }
+  }
+}
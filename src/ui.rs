@@ -1,191 +1,1749 @@
-use crate::db::{Move, Game, InsertionError};
-use crate::queries::{game_from_id, movements_from_game, games_from_player, related_games_from_game};
-use cursive::event::{Event, Key, EventResult};
+use crate::db::{Move, Game, GameTags, InsertionError, RelatedGame, RelatedGamesOptions, RelatedOrder, GameOrder, GameCursor, GameListQuery, PlayerProfile, ColorScore, HeadToHead, position_from_fen, position_hash, pawn_structure_hash, material_balance, captured_pieces, game_pgn};
+use crate::queries::{game_from_id, movements_from_game, games_from_player_page, games_count_from_player, related_games_from_game, movement_and_games_from_position, games_from_pawn_structure, games_from_pattern, players_matching, player_profile, opening_lines_from_player, head_to_head, novelty_from_game, tags_from_game};
+use crate::repertoire::{Repertoire, RepertoireNode};
+use crate::pattern::PiecePattern;
+use crate::analysis::{Analysis, AnalysisNode, parse_move, move_between};
+use crate::theme::BoardTheme;
+use crate::command::{Command, Completion, completions, common_prefix};
+use crate::clock::{GameClocks, TimeControl, format_clock, format_spent};
+use cursive::direction::Direction;
+use cursive::event::{Event, EventResult, Key, MouseButton, MouseEvent};
 use cursive::theme::{ColorStyle, Color, BaseColor, Style, Effect};
-use cursive::view::{Resizable, ScrollStrategy};
-use pgn_reader::{Square, Role, Color as PieceColor};
-use shakmaty::{Board, Piece, Chess, Position};
-use cursive::views::{Dialog, LinearLayout, EditView, TextView, DummyView, Panel, ScrollView, SelectView};
+use cursive::view::{CannotFocus, Finder, Resizable, ScrollStrategy, Selector, ViewNotFound, ViewWrapper};
+use pgn_reader::{Square, Color as PieceColor};
+use shakmaty::{Board, Piece, Chess, Position, Move as ChessMove, zobrist::Zobrist64};
+use cursive::views::{BoxedView, Dialog, LinearLayout, EditView, OnEventView, TextView, DummyView, Panel, ScrollView, SelectView};
 use cursive::traits::Nameable;
-use cursive::{Cursive, CursiveExt, View};
-use sqlx::{PgConnection, Connection};
-use std::cell::RefCell;
+use cursive::utils::markup::StyledString;
+use cursive::{Cursive, CursiveExt, Printer, Rect, Vec2, View};
+use sqlx::{PgConnection, PgPool, postgres::PgPoolOptions};
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
 use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use tokio::task::JoinHandle;
+
+/// State shared by every screen, kept as cursive's user data.
+#[derive(Clone)]
+pub struct Session {
+  pub related: RelatedGamesOptions,
+  /// Theme of the boards opened next, changed along with the one of the board shown.
+  pub board_theme: BoardTheme,
+  /// Whether boards are opened with large squares.
+  pub large_board: bool,
+  /// A position reached by fewer earlier games than this is a novelty.
+  pub novelty_min_games: i64,
+  runtime: Arc<Runtime>,
+  pool: PgPool,
+  /// Query running for each task, replaced when the same task starts again.
+  queries: Rc<RefCell<HashMap<&'static str, PendingQuery>>>,
+  next_ticket: Rc<Cell<u64>>,
+}
+
+/// Result of a query, whose type is only known to the callback waiting for it.
+type QueryResult = Box<dyn Any + Send>;
+type QueryCallback = Box<dyn FnOnce(&mut Cursive, QueryResult)>;
+
+/// A query running in the background, and what to do with its result back on the UI thread.
+struct PendingQuery {
+  ticket: u64,
+  handle: JoinHandle<()>,
+  on_done: QueryCallback,
+}
+
+/// Errors after which a query is tried again, the pool replacing the connection that dropped.
+fn is_connection_error(err: &InsertionError) -> bool {
+  matches!(err, InsertionError::DbError(sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut))
+}
+
+/// Runs `query` in the background, cancelling the one already running for `task`, and hands its
+/// result to `on_done` through the `cb_sink`, unless it was cancelled in between.
+fn run_query<T, Q, F, D>(siv: &mut Cursive, task: &'static str, query: Q, on_done: D)
+where
+  T: Send + 'static,
+  Q: FnOnce(PgPool) -> F + Clone + Send + 'static,
+  F: Future<Output = Result<T, InsertionError>> + Send,
+  D: FnOnce(&mut Cursive, Result<T, InsertionError>) + 'static,
+{
+  cancel_query(siv, task);
+  let cb_sink = siv.cb_sink().clone();
+  let session = siv.user_data::<Session>().unwrap();
+  let ticket = session.next_ticket.get();
+  session.next_ticket.set(ticket + 1);
+  let pool = session.pool.clone();
+  let handle = session.runtime.spawn(async move {
+    let result = match query.clone()(pool.clone()).await {
+      Err(err) if is_connection_error(&err) => query(pool).await,
+      result => result,
+    };
+    let result: QueryResult = Box::new(result);
+    cb_sink.send(Box::new(move |s: &mut Cursive| finish_query(s, task, ticket, result))).ok();
+  });
+  let on_done = Box::new(move |s: &mut Cursive, result: QueryResult| {
+    let result = result.downcast::<Result<T, InsertionError>>().expect("query result of another type");
+    on_done(s, *result)
+  });
+  session.queries.borrow_mut().insert(task, PendingQuery { ticket, handle, on_done });
+}
+
+fn finish_query(siv: &mut Cursive, task: &'static str, ticket: u64, result: QueryResult) {
+  let pending = siv.user_data::<Session>().and_then(|session| {
+    let mut queries = session.queries.borrow_mut();
+    match queries.get(task) {
+      Some(pending) if pending.ticket == ticket => queries.remove(task),
+      _ => None,
+    }
+  });
+  if let Some(pending) = pending {
+    (pending.on_done)(siv, result)
+  }
+}
+
+fn cancel_query(siv: &mut Cursive, task: &'static str) {
+  let pending = siv.user_data::<Session>().and_then(|session| session.queries.borrow_mut().remove(task));
+  if let Some(pending) = pending {
+    pending.handle.abort();
+  }
+}
+
+/// Like `run_query`, behind a loading dialog that cancels the query when closed.
+fn load<T, Q, F, D>(siv: &mut Cursive, task: &'static str, label: &str, query: Q, on_done: D)
+where
+  T: Send + 'static,
+  Q: FnOnce(PgPool) -> F + Clone + Send + 'static,
+  F: Future<Output = Result<T, InsertionError>> + Send,
+  D: FnOnce(&mut Cursive, Result<T, InsertionError>) + 'static,
+{
+  let name = format!("loading_{task}");
+  let loading = Dialog::text(format!("{label}..."))
+    .title("Loading")
+    .button("Cancel", move |s| {
+      cancel_query(s, task);
+      s.pop_layer();
+    })
+    .with_name(name.clone());
+  siv.add_layer(loading);
+  run_query(siv, task, query, move |s, result| {
+    if let Some(position) = s.screen_mut().find_layer_from_name(&name) {
+      s.screen_mut().remove_layer(position);
+    }
+    on_done(s, result)
+  });
+}
 
 #[derive(Debug)]
 pub struct BoardState {
   game: Game,
+  tags: GameTags,
   moves: Vec<Move>,
-  related_games: Vec<Vec<(Move, Game)>>,
-  curr_move_idx: usize
+  /// Position after each ply, from the starting one.
+  positions: Vec<Chess>,
+  /// Move played at each ply.
+  played: Vec<ChessMove>,
+  /// Material balance of each position, from the starting one.
+  balances: Vec<i32>,
+  clocks: GameClocks,
+  related_games: Vec<Vec<RelatedGame>>,
+  /// Game round of the move that left the positions known from earlier games.
+  novelty: Option<i32>,
+  curr_move_idx: usize,
+  /// Games left to follow a related game, each at the position it was left, most recent last.
+  trail: Vec<BoardState>,
+  /// Variations tried from the current ply, shown instead of the game while it exists.
+  analysis: Option<Analysis>,
+  /// Square under the keyboard cursor, while picking a move on the board.
+  cursor: Option<Square>,
+  /// Square picked as the origin of the next move.
+  selected: Option<Square>,
+  /// Side drawn at the bottom of the board.
+  side: PieceColor,
+  theme: BoardTheme,
+  /// Whether squares are drawn twice as large, when there is room for it.
+  large_board: bool,
 }
 
 impl BoardState {
-  async fn build(conn: &mut PgConnection, game: Game) -> Result<Self, InsertionError> {
-    let moves = movements_from_game(conn, game.id.clone()).await?;
-    let related_games = related_games_from_game(conn, game.id.id).await?;
+  /// Loads a game, its novelty being the first position reached by fewer than `novelty_min_games` earlier games.
+  async fn build(conn: &mut PgConnection, game: Game, related: &RelatedGamesOptions, novelty_min_games: i64) -> Result<Self, InsertionError> {
+    let (moves, clocks) = movements_from_game(conn, game.id.clone()).await?;
+    let (positions, played) = replay_moves(&moves)?;
+    let balances = positions.iter().map(|position| material_balance(position.board())).collect();
+    let related_games = related_games_from_game(conn, game.id.id, related).await?;
+    let novelty = novelty_from_game(conn, game.id.id, novelty_min_games).await?;
+    let tags = tags_from_game(conn, game.id.id).await?;
+    let clocks = GameClocks { time_control: tags.time_control.as_deref().and_then(TimeControl::parse), clocks };
     Ok(BoardState {
       game,
+      tags,
       moves,
+      positions,
+      played,
+      balances,
+      clocks,
       related_games,
-      curr_move_idx: 0
+      novelty,
+      curr_move_idx: 0,
+      trail: Vec::new(),
+      analysis: None,
+      cursor: None,
+      selected: None,
+      side: PieceColor::White,
+      theme: BoardTheme::default(),
+      large_board: false,
     })
   }
 
-  fn game_up_to_move(&self, move_idx: usize) -> Chess {
-    let mut chess = Chess::default();
-    for movement in self.moves.iter().take(move_idx) {
-      let mov = movement.san_plus.0.san.to_move(&chess).expect("invalid move in database");
-      chess.play_unchecked(&mov);
+  fn game_up_to_move(&self, move_idx: usize) -> &Chess {
+    &self.positions[move_idx.min(self.positions.len() - 1)]
+  }
+
+  /// Position shown, the analysis one if there is one.
+  fn position(&self) -> &Chess {
+    match &self.analysis {
+      Some(analysis) => analysis.position(),
+      None => self.game_up_to_move(self.curr_move_idx),
     }
-    chess
   }
 
-  fn current_board(&self) -> Board {
-    let game = self.game_up_to_move(self.curr_move_idx);
-    game.board().clone()
+  fn current_board(&self) -> &Board {
+    self.position().board()
   }
 
-  fn last_move_from_square(&self) -> Option<Square> {
-    if self.curr_move_idx == 0 {
-      return None
+  fn last_move(&self) -> Option<&ChessMove> {
+    match self.analysis.as_ref().and_then(Analysis::current_node) {
+      Some(node) => Some(&node.mov),
+      None => self.curr_move_idx.checked_sub(1).and_then(|idx| self.played.get(idx)),
     }
-    let chess = self.game_up_to_move(self.curr_move_idx - 1);
-    self.moves
-      .get(self.curr_move_idx - 1)
-      .and_then(|mvmt| mvmt.san_plus.0.san.to_move(&chess).expect("invalid move in database").from())
   }
+
+  fn last_move_from_square(&self) -> Option<Square> {
+    self.last_move().and_then(ChessMove::from)
+  }
+
   fn last_move_to_square(&self) -> Option<Square> {
-    if self.curr_move_idx == 0 {
-      return None
-    }
-    let chess = self.game_up_to_move(self.curr_move_idx - 1);
-    self.moves
-      .get(self.curr_move_idx - 1)
-      .map(|mvmt| mvmt.san_plus.0.san.to_move(&chess).expect("invalid move in database").to())
+    self.last_move().map(ChessMove::to)
   }
 }
 
-fn fetch_game(db_url: String, game_id: i32) -> Result<BoardState, InsertionError> {
-  let rt = tokio::runtime::Builder::new_multi_thread().worker_threads(4).enable_all().build().unwrap();
-  rt.block_on(async {
-    let mut conn = PgConnection::connect(&db_url).await?;
-    let game = game_from_id(&mut conn, game_id).await?;
-    let board_state = BoardState::build(&mut conn, game).await?;
-    Ok(board_state)
-  })
+/// Every position of a game, from the starting one, and the moves played between them.
+fn replay_moves(moves: &[Move]) -> Result<(Vec<Chess>, Vec<ChessMove>), InsertionError> {
+  let mut positions = vec![Chess::default()];
+  let mut played = Vec::with_capacity(moves.len());
+  for movement in moves {
+    let mut chess = positions[positions.len() - 1].clone();
+    let mov = movement.san_plus.0.san.to_move(&chess)
+      .map_err(|_| InsertionError::IlegalMove(movement.san_plus.0.clone()))?;
+    chess.play_unchecked(&mov);
+    positions.push(chess);
+    played.push(mov);
+  }
+  Ok((positions, played))
 }
-fn fetch_games_from_player(db_url: &str, player_name: &str) -> Result<Vec<Game>, InsertionError> {
-  let rt = tokio::runtime::Builder::new_multi_thread().worker_threads(4).enable_all().build().unwrap();
-  rt.block_on(async {
-    let mut conn = PgConnection::connect(db_url).await?;
-    let games = games_from_player(&mut conn, player_name).await?;
-    Ok(games)
-  })
+
+async fn fetch_game(pool: PgPool, related: RelatedGamesOptions, novelty_min_games: i64, game_id: i32) -> Result<BoardState, InsertionError> {
+  let mut conn = pool.acquire().await?;
+  let game = game_from_id(&mut conn, game_id).await?;
+  BoardState::build(&mut conn, game, &related, novelty_min_games).await
+}
+
+async fn fetch_related_games(pool: PgPool, related: RelatedGamesOptions, game_id: i32) -> Result<Vec<Vec<RelatedGame>>, InsertionError> {
+  let mut conn = pool.acquire().await?;
+  related_games_from_game(&mut conn, game_id, &related).await
+}
+
+/// One page of the player's games, and on the first page how many games the list holds.
+async fn fetch_games_from_player(pool: PgPool, player_name: String, list: GameListQuery, after: Option<GameCursor>) -> Result<(Vec<(Game, GameCursor)>, Option<i64>), InsertionError> {
+  let mut conn = pool.acquire().await?;
+  let games = games_from_player_page(&mut conn, &player_name, &list, after.as_ref(), GAMES_PAGE_SIZE).await?;
+  let games_number = match after {
+    Some(_) => None,
+    None => Some(games_count_from_player(&mut conn, &player_name, &list.filter, list.opponent.as_deref()).await?),
+  };
+  Ok((games, games_number))
+}
+
+async fn fetch_games_from_position(pool: PgPool, board_hash: Zobrist64) -> Result<Vec<(Move, Game)>, InsertionError> {
+  let mut conn = pool.acquire().await?;
+  movement_and_games_from_position(&mut conn, board_hash).await
+}
+
+async fn fetch_games_from_pawn_structure(pool: PgPool, pawn_hash: Zobrist64, except_game: i32) -> Result<Vec<(Move, Game)>, InsertionError> {
+  let mut conn = pool.acquire().await?;
+  games_from_pawn_structure(&mut conn, pawn_hash, Some(except_game)).await
+}
+
+async fn fetch_games_from_pattern(pool: PgPool, pattern: PiecePattern) -> Result<Vec<(Move, Game)>, InsertionError> {
+  let mut conn = pool.acquire().await?;
+  games_from_pattern(&mut conn, &pattern).await
+}
+
+async fn fetch_players_matching(pool: PgPool, search: String) -> Result<Vec<(String, i64)>, InsertionError> {
+  let mut conn = pool.acquire().await?;
+  players_matching(&mut conn, &search, 10).await
+}
+
+async fn fetch_player_profile(pool: PgPool, player_name: String) -> Result<PlayerProfile, InsertionError> {
+  let mut conn = pool.acquire().await?;
+  player_profile(&mut conn, &player_name).await
+}
+
+async fn fetch_repertoire(pool: PgPool, player_name: String, as_white: bool, depth: i32, min_games: i64) -> Result<Repertoire, InsertionError> {
+  let mut conn = pool.acquire().await?;
+  let lines = opening_lines_from_player(&mut conn, &player_name, as_white, depth).await?;
+  Ok(Repertoire::build(player_name, as_white, lines, min_games))
 }
 
-pub fn cli_entrypoint(db_url: String) {
+async fn fetch_head_to_head(pool: PgPool, player_name: String, opponent: String) -> Result<HeadToHead, InsertionError> {
+  let mut conn = pool.acquire().await?;
+  head_to_head(&mut conn, &player_name, &opponent).await
+}
+
+pub fn cli_entrypoint(db_url: String, related: RelatedGamesOptions, board_theme: BoardTheme, large_board: bool, novelty_min_games: i64) {
+  let runtime = tokio::runtime::Builder::new_multi_thread().worker_threads(2).enable_all().build().unwrap();
+  let pool = {
+    let _runtime = runtime.enter();
+    // connections are only opened when needed, and opened again when dropped.
+    PgPoolOptions::new()
+      .max_connections(4)
+      .acquire_timeout(Duration::from_secs(10))
+      .connect_lazy(&db_url)
+      .expect("Invalid database url")
+  };
   let mut siv = cursive::default();
   siv.set_window_title("Nixchess");
-  siv.set_user_data(db_url);
+  siv.set_user_data(Session {
+    related,
+    board_theme,
+    large_board,
+    novelty_min_games,
+    runtime: Arc::new(runtime),
+    pool,
+    queries: Rc::new(RefCell::new(HashMap::new())),
+    next_ticket: Rc::new(Cell::new(0)),
+  });
   siv.add_global_callback('q', |s| { s.quit(); });
   siv.add_global_callback('\'', Cursive::toggle_debug_console);
+  siv.add_global_callback(':', |s| s.add_layer(command_palette(None)));
   siv.add_layer(player_selector());
   siv.run_crossterm().expect("Could not run on crossterm backend");
 }
 
 fn player_selector() -> impl View {
-  Dialog::around(EditView::new().with_name("player_name"))
+  let search = EditView::new()
+    .on_edit(update_player_matches)
+    .on_submit(|s, typed| {
+      let selected = s.call_on_name("player_matches", |v: &mut SelectView<String>| v.selection()).flatten();
+      let player_name = selected.map(|name| (*name).clone()).unwrap_or_else(|| typed.to_string());
+      choose_player(s, &player_name)
+    })
+    .with_name("player_name");
+  let matches = SelectView::<String>::new()
+    .on_submit(|s, player_name: &String| choose_player(s, player_name))
+    .with_name("player_matches");
+  let layout = LinearLayout::vertical()
+    .child(search)
+    .child(TextView::new("").with_name("player_matches_status"))
+    .child(matches)
+    .min_width(40);
+  Dialog::around(layout)
     .title("Player name:")
     .button("Ok", move |s| {
       let player_name = s.call_on_name("player_name", |v: &mut EditView| v.get_content()).unwrap();
-      let db_url = s.user_data::<String>().unwrap().clone();
-      let games = fetch_games_from_player(&db_url, &player_name);
-      match games {
-        Ok(games) => {
-          s.add_layer(game_selector((*player_name).clone(), games, db_url))
-        },
-        Err(err) => error_pop_up(s, err),
+      choose_player(s, &player_name)
+    })
+    .button("Position", |s| s.add_layer(position_selector()))
+    .button("Pattern", |s| s.add_layer(pattern_selector()))
+}
+
+fn update_player_matches(siv: &mut Cursive, search: &str, _cursor: usize) {
+  let search = search.trim().to_string();
+  if search.is_empty() {
+    cancel_query(siv, "player_matches");
+    return show_player_matches(siv, Ok(Vec::new()), "");
+  }
+  siv.call_on_name("player_matches_status", |v: &mut TextView| v.set_content("Searching..."));
+  run_query(siv, "player_matches", move |pool| fetch_players_matching(pool, search.clone()), |s, players| {
+    let status = match &players {
+      Ok(players) if players.is_empty() => "No player matches",
+      _ => "",
+    };
+    show_player_matches(s, players, status)
+  });
+}
+
+fn show_player_matches(siv: &mut Cursive, players: Result<Vec<(String, i64)>, InsertionError>, status: &str) {
+  let status = match &players {
+    Ok(_) => status.to_string(),
+    Err(err) => format!("{err:?}"),
+  };
+  siv.call_on_name("player_matches_status", |v: &mut TextView| v.set_content(status));
+  siv.call_on_name("player_matches", |v: &mut SelectView<String>| {
+    v.clear();
+    for (player_name, games) in players.unwrap_or_default() {
+      v.add_item(format!("{player_name} ({games} games)"), player_name);
+    }
+  });
+}
+
+fn choose_player(siv: &mut Cursive, player_name: &str) {
+  cancel_query(siv, "player_matches");
+  let player_name = player_name.to_string();
+  let query_player = player_name.clone();
+  load(siv, "player_profile", &format!("Loading {player_name}'s profile"),
+    move |pool| fetch_player_profile(pool, query_player),
+    move |s, profile| match profile {
+      Ok(profile) => s.add_layer(player_profile_view(player_name, &profile)),
+      Err(err) => error_pop_up(s, err),
+    });
+}
+
+fn score_description(score: &ColorScore) -> String {
+  let percentage = if score.games > 0 {
+    100.0 * (score.wins as f64 + score.draws as f64 / 2.0) / score.games as f64
+  } else {
+    0.0
+  };
+  format!("{} games, +{} ={} -{} ({percentage:.1}%)", score.games, score.wins, score.draws, score.losses)
+}
+
+fn counts_description(counts: &[(String, i64)]) -> String {
+  if counts.is_empty() {
+    return "-".to_string();
+  }
+  counts.iter()
+    .map(|(name, count)| format!("{name} ({count})"))
+    .collect::<Vec<_>>()
+    .join(", ")
+}
+
+const SPARKLINE_BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// One block character per value, scaled between the smallest and the largest.
+pub fn sparkline(values: &[i32]) -> String {
+  let (Some(min), Some(max)) = (values.iter().min(), values.iter().max()) else {
+    return String::new();
+  };
+  let range = (max - min).max(1) as usize;
+  values.iter()
+    .map(|value| SPARKLINE_BLOCKS[(value - min) as usize * (SPARKLINE_BLOCKS.len() - 1) / range])
+    .collect()
+}
+
+/// One block character per value, half height standing for zero and full height for the largest
+/// value of either sign, so positive and negative values stay apart.
+pub fn centered_sparkline(values: &[i32]) -> String {
+  let bound = values.iter().map(|value| value.abs()).max().unwrap_or(0).max(1);
+  let top = SPARKLINE_BLOCKS.len() as i32 - 1;
+  values.iter()
+    .map(|value| SPARKLINE_BLOCKS[((value + bound) * top / (2 * bound)) as usize])
+    .collect()
+}
+
+fn player_profile_view(player_name: String, profile: &PlayerProfile) -> impl View {
+  let ratings: Vec<i32> = profile.rating_history.iter().map(|(_, rating)| *rating).collect();
+  let rating = match (profile.rating_history.first(), profile.rating_history.last()) {
+    (Some((first_month, first)), Some((last_month, last))) => format!(
+      "{first} ({}) -> {last} ({}) {}", first_month.format("%Y-%m"), last_month.format("%Y-%m"), sparkline(&ratings)),
+    _ => "-".to_string(),
+  };
+  let length = match (profile.average_plies, profile.median_plies) {
+    (Some(average), Some(median)) => format!("{average:.0} plies on average, {median:.0} median"),
+    _ => "-".to_string(),
+  };
+  let lines = LinearLayout::vertical()
+    .child(TextView::new(format!("Games: {}", profile.games)))
+    .child(TextView::new(format!("As white: {}", score_description(&profile.as_white))))
+    .child(TextView::new(format!("As black: {}", score_description(&profile.as_black))))
+    .child(TextView::new(format!("Rating: {rating}")))
+    .child(TextView::new(format!("Opponents: {}", counts_description(&profile.opponents))))
+    .child(TextView::new(format!("Openings as white: {}", counts_description(&profile.white_openings))))
+    .child(TextView::new(format!("Openings as black: {}", counts_description(&profile.black_openings))))
+    .child(TextView::new(format!("Game length: {length}")));
+  let games_player = player_name.clone();
+  Dialog::around(lines.max_width(80))
+    .title(player_name.clone())
+    .button("Games", move |s| open_game_list(s, &games_player, None))
+    .button("Repertoire", {
+      let player_name = player_name.clone();
+      move |s| s.add_layer(repertoire_form(player_name.clone()))
+    })
+    .button("Head to head", move |s| s.add_layer(opponent_selector(player_name.clone())))
+    .button("Back", |s| { s.pop_layer(); })
+}
+
+fn opponent_selector(player_name: String) -> impl View {
+  let submit_player = player_name.clone();
+  let opponent = EditView::new()
+    .on_submit(move |s, opponent| choose_opponent(s, &submit_player, opponent))
+    .with_name("opponent_name")
+    .min_width(30);
+  Dialog::around(opponent)
+    .title(format!("{player_name} against:"))
+    .button("Ok", move |s| {
+      let opponent = s.call_on_name("opponent_name", |v: &mut EditView| v.get_content()).unwrap();
+      choose_opponent(s, &player_name, &opponent)
+    })
+    .button("Cancel", |s| { s.pop_layer(); })
+}
+
+fn choose_opponent(siv: &mut Cursive, player_name: &str, opponent: &str) {
+  let (player_name, opponent) = (player_name.to_string(), opponent.to_string());
+  let (query_player, query_opponent) = (player_name.clone(), opponent.clone());
+  load(siv, "head_to_head", &format!("Loading {player_name}'s games against {opponent}"),
+    move |pool| fetch_head_to_head(pool, query_player, query_opponent),
+    move |s, head_to_head| match head_to_head {
+      Ok(head_to_head) => {
+        s.pop_layer();
+        s.add_layer(head_to_head_view(player_name, opponent, &head_to_head))
+      },
+      Err(err) => error_pop_up(s, err),
+    });
+}
+
+fn head_to_head_view(player_name: String, opponent: String, head_to_head: &HeadToHead) -> impl View {
+  let timeline = match (head_to_head.timeline.first(), head_to_head.timeline.last()) {
+    (Some((first, _)), Some((last, _))) => format!(
+      "{} ({} -> {})",
+      head_to_head.timeline.iter().map(|(_, outcome)| outcome).collect::<String>(),
+      first.format("%Y-%m-%d"),
+      last.format("%Y-%m-%d")),
+    _ => "-".to_string(),
+  };
+  let lines = LinearLayout::vertical()
+    .child(TextView::new(format!("Games: {}", head_to_head.timeline.len())))
+    .child(TextView::new(format!("{player_name} as white: {}", score_description(&head_to_head.as_white))))
+    .child(TextView::new(format!("{player_name} as black: {}", score_description(&head_to_head.as_black))))
+    .child(TextView::new(format!("Openings: {}", counts_description(&head_to_head.openings))))
+    .child(TextView::new(format!("Results: {timeline}")));
+  Dialog::around(lines.max_width(80))
+    .title(format!("{player_name} vs {opponent}"))
+    .button("Games", move |s| open_game_list(s, &player_name, Some(opponent.clone())))
+    .button("Back", |s| { s.pop_layer(); })
+}
+
+fn repertoire_form(player_name: String) -> impl View {
+  let color = SelectView::new()
+    .popup()
+    .item("White", true)
+    .item("Black", false)
+    .with_name("repertoire_color");
+  let form = LinearLayout::vertical()
+    .child(LinearLayout::horizontal().child(TextView::new("Color:     ")).child(color))
+    .child(LinearLayout::horizontal()
+      .child(TextView::new("Depth:     "))
+      .child(EditView::new().content("12").with_name("repertoire_depth").fixed_width(6)))
+    .child(LinearLayout::horizontal()
+      .child(TextView::new("Min games: "))
+      .child(EditView::new().content("2").with_name("repertoire_min_games").fixed_width(6)));
+  Dialog::around(form)
+    .title(format!("Repertoire of {player_name}"))
+    .button("Ok", move |s| {
+      let as_white = s.call_on_name("repertoire_color", |v: &mut SelectView<bool>| v.selection())
+        .flatten()
+        .map_or(true, |as_white| *as_white);
+      let depth = s.call_on_name("repertoire_depth", |v: &mut EditView| v.get_content().parse::<i32>().ok()).flatten();
+      let min_games = s.call_on_name("repertoire_min_games", |v: &mut EditView| v.get_content().parse::<i64>().ok()).flatten();
+      let (Some(depth), Some(min_games)) = (depth, min_games) else {
+        return error_pop_up(s, "Depth and minimum games must be numbers");
       };
+      let query_player = player_name.clone();
+      load(s, "repertoire", &format!("Building {player_name}'s repertoire"),
+        move |pool| fetch_repertoire(pool, query_player, as_white, depth, min_games),
+        |s, repertoire| match repertoire {
+          Ok(repertoire) => {
+            s.pop_layer();
+            s.add_layer(repertoire_view(repertoire))
+          },
+          Err(err) => error_pop_up(s, err),
+        });
     })
+    .button("Cancel", |s| { s.pop_layer(); })
 }
 
-fn game_selector(player_name: String, games: Vec<Game>, db_url: String) -> impl View {
-  let mut game_selector = SelectView::new();
-  let games_number = games.len();
-  for game in games {
-    let game_description = if game.white == player_name {
-      format!("[W] vs {} - {} @ {}", game.black, game.event, game.datetime)
-    } else {
-      format!("[B] vs {} - {} @ {}", game.white, game.event, game.datetime)
+/// Repertoire tree with the set of expanded nodes, each node being identified by its path of child indexes.
+struct RepertoireTree {
+  repertoire: Repertoire,
+  expanded: HashSet<Vec<usize>>,
+}
+
+fn push_repertoire_lines(tree: &RepertoireTree, nodes: &[RepertoireNode], path: &mut Vec<usize>, lines: &mut Vec<(String, Vec<usize>)>) {
+  for (index, node) in nodes.iter().enumerate() {
+    path.push(index);
+    let expanded = tree.expanded.contains(path);
+    let marker = match (node.children.is_empty(), expanded) {
+      (true, _) => ' ',
+      (false, true) => '▾',
+      (false, false) => '▸',
     };
-    game_selector.add_item(game_description, game);
+    let score = node.score().map_or("-".to_string(), |score| format!("{:.0}%", score * 100.0));
+    let indent = "  ".repeat(path.len() - 1);
+    lines.push((format!("{indent}{marker} {}  {} games, {score}", node.numbered_san(), node.games), path.clone()));
+    if expanded {
+      push_repertoire_lines(tree, &node.children, path, lines);
+    }
+    path.pop();
+  }
+}
+
+fn refresh_repertoire_tree(siv: &mut Cursive, tree: &RepertoireTree, selected: Option<&Vec<usize>>) {
+  let mut lines = Vec::new();
+  push_repertoire_lines(tree, &tree.repertoire.children, &mut Vec::new(), &mut lines);
+  siv.call_on_name("repertoire_tree", |v: &mut SelectView<Vec<usize>>| {
+    v.clear();
+    let selected_id = lines.iter().position(|(_, path)| Some(path) == selected);
+    v.add_all(lines);
+    if let Some(selected_id) = selected_id {
+      v.set_selection(selected_id);
+    }
+  });
+}
+
+fn repertoire_view(repertoire: Repertoire) -> impl View {
+  let color = if repertoire.as_white { "white" } else { "black" };
+  let title = format!("{} as {color}: {} games", repertoire.player, repertoire.games);
+  let export_name = format!("{}_{color}.pgn", file_name_part(&repertoire.player));
+  let tree = Rc::new(RefCell::new(RepertoireTree { repertoire, expanded: HashSet::new() }));
+  let mut lines = Vec::new();
+  push_repertoire_lines(&tree.borrow(), &tree.borrow().repertoire.children, &mut Vec::new(), &mut lines);
+  let toggle_tree = tree.clone();
+  let list = SelectView::new()
+    .with_all(lines)
+    .on_submit(move |s, path: &Vec<usize>| {
+      let mut tree = toggle_tree.borrow_mut();
+      if !tree.expanded.remove(path) {
+        tree.expanded.insert(path.clone());
+      }
+      refresh_repertoire_tree(s, &tree, Some(path));
+    })
+    .with_name("repertoire_tree");
+  Dialog::around(ScrollView::new(list).show_scrollbars(true).max_height(20).min_width(50))
+    .title(title)
+    .button("Export", move |s| s.add_layer(repertoire_export(tree.clone(), export_name.clone())))
+    .button("Back", |s| { s.pop_layer(); })
+}
+
+fn repertoire_export(tree: Rc<RefCell<RepertoireTree>>, file_name: String) -> impl View {
+  let submit_tree = tree.clone();
+  let file = EditView::new()
+    .content(file_name)
+    .on_submit(move |s, file_name| save_repertoire(s, &submit_tree, file_name))
+    .with_name("repertoire_file")
+    .min_width(40);
+  Dialog::around(file)
+    .title("Export as PGN to:")
+    .button("Ok", move |s| {
+      let file_name = s.call_on_name("repertoire_file", |v: &mut EditView| v.get_content()).unwrap();
+      save_repertoire(s, &tree, &file_name)
+    })
+    .button("Cancel", |s| { s.pop_layer(); })
+}
+
+fn save_repertoire(siv: &mut Cursive, tree: &Rc<RefCell<RepertoireTree>>, file_name: &str) {
+  let pgn = tree.borrow().repertoire.to_pgn();
+  siv.pop_layer();
+  match std::fs::write(file_name, pgn) {
+    Ok(()) => siv.add_layer(Dialog::info(format!("Repertoire saved to {file_name}"))),
+    Err(err) => error_pop_up(siv, err),
   }
-  game_selector.set_on_submit(move |s, game| {
+}
+
+fn open_game_list(siv: &mut Cursive, player_name: &str, opponent: Option<String>) {
+  let game_list = Rc::new(RefCell::new(GameList {
+    player_name: player_name.to_string(),
+    query: GameListQuery { order: GameOrder::Date, descending: true, filter: String::new(), opponent },
+    next_page: None,
+    exhausted: false,
+    focus_pending: true,
+  }));
+  siv.add_layer(game_selector(game_list.clone()));
+  reload_game_list(siv, &game_list);
+}
+
+fn position_selector() -> impl View {
+  Dialog::around(EditView::new().with_name("position_fen").min_width(60))
+    .title("Position FEN:")
+    .button("Ok", |s| {
+      let fen = s.call_on_name("position_fen", |v: &mut EditView| v.get_content()).unwrap();
+      find_position(s, &fen)
+    })
+    .button("Cancel", |s| { s.pop_layer(); })
+}
+
+fn find_position(siv: &mut Cursive, fen: &str) {
+  let board_hash = match position_from_fen(fen) {
+    Ok(position) => position_hash(&position),
+    Err(err) => return error_pop_up(siv, err),
+  };
+  load(siv, "position", "Looking up the position",
+    move |pool| fetch_games_from_position(pool, board_hash),
+    |s, games| match games {
+      Ok(games) => {
+        let title = format!("{} games reach this position", games.len());
+        s.add_layer(position_game_selector(title, games))
+      },
+      Err(err) => error_pop_up(s, err),
+    });
+}
+
+fn pattern_selector() -> impl View {
+  Dialog::around(EditView::new().with_name("piece_pattern").min_width(40))
+    .title("Pieces, like Nd5 pd6 !b@light:")
+    .button("Ok", |s| {
+      let pattern = s.call_on_name("piece_pattern", |v: &mut EditView| v.get_content()).unwrap();
+      find_pattern(s, &pattern)
+    })
+    .button("Cancel", |s| { s.pop_layer(); })
+}
+
+fn find_pattern(siv: &mut Cursive, pattern: &str) {
+  let pattern = match PiecePattern::parse(pattern) {
+    Ok(pattern) => pattern,
+    Err(err) => return error_pop_up(siv, err),
+  };
+  load(siv, "pattern", "Looking up the pattern",
+    move |pool| fetch_games_from_pattern(pool, pattern.clone()),
+    |s, games| match games {
+      Ok(games) => {
+        let title = format!("{} games reach this pattern", games.len());
+        s.add_layer(position_game_selector(title, games))
+      },
+      Err(err) => error_pop_up(s, err),
+    });
+}
+
+fn position_game_selector(title: String, games: Vec<(Move, Game)>) -> impl View {
+  let mut game_selector = SelectView::new();
+  for (next_move, game) in games {
+    let white_elo = game.white_elo.map_or("?".to_string(), |elo| elo.to_string());
+    let black_elo = game.black_elo.map_or("?".to_string(), |elo| elo.to_string());
+    let game_description = format!("{} - {} ({white_elo}) vs {} ({black_elo}) - {}",
+      next_move.numbered_san(), game.white, game.black, game.event);
+    game_selector.add_item(game_description, (next_move, game));
+  }
+  game_selector.set_on_submit(move |s, (next_move, game): &(Move, Game)| {
     s.pop_layer();
-    show_game(s, game, db_url.clone())
+    show_game(s, game, (next_move.game_round - 1) as usize, PieceColor::White)
   });
-  Dialog::around(ScrollView::new(game_selector).show_scrollbars(true).max_height(10)).title(format!("{games_number} games played by {player_name}"))
+  Dialog::around(ScrollView::new(game_selector).show_scrollbars(true).max_height(10))
+    .title(title)
+    .button("Back", |s| { s.pop_layer(); })
+}
+
+const GAMES_PAGE_SIZE: i64 = 50;
+
+/// A player's games as shown by `game_selector`, fetched one page at a time.
+struct GameList {
+  player_name: String,
+  query: GameListQuery,
+  next_page: Option<GameCursor>,
+  exhausted: bool,
+  /// Whether the list is focused once its first page arrives, as when it was just opened.
+  focus_pending: bool,
+}
+
+fn game_selector(game_list: Rc<RefCell<GameList>>) -> impl View {
+  let order_list = game_list.clone();
+  let order = SelectView::new()
+    .popup()
+    .item("Date", GameOrder::Date)
+    .item("Elo", GameOrder::Elo)
+    .item("Opponent", GameOrder::Opponent)
+    .item("Result", GameOrder::Result)
+    .item("Length", GameOrder::Length)
+    .on_submit(move |s, order: &GameOrder| {
+      order_list.borrow_mut().query.order = *order;
+      reload_game_list(s, &order_list);
+    });
+  let direction_list = game_list.clone();
+  let direction = SelectView::new()
+    .popup()
+    .item("Descending", true)
+    .item("Ascending", false)
+    .on_submit(move |s, descending: &bool| {
+      direction_list.borrow_mut().query.descending = *descending;
+      reload_game_list(s, &direction_list);
+    });
+  let filter_list = game_list.clone();
+  let filter = EditView::new()
+    .on_edit(move |s, filter, _| {
+      filter_list.borrow_mut().query.filter = filter.trim().to_string();
+      reload_game_list(s, &filter_list);
+    })
+    .min_width(20);
+  let controls = LinearLayout::horizontal()
+    .child(TextView::new("Sort: "))
+    .child(order)
+    .child(DummyView)
+    .child(direction)
+    .child(DummyView)
+    .child(TextView::new("Filter: "))
+    .child(filter);
+  let select_list = game_list.clone();
+  let submit_list = game_list;
+  let games = SelectView::<Game>::new()
+    .on_select(move |s, _| {
+      let near_end = s.call_on_name("game_list", |v: &mut SelectView<Game>| {
+        v.selected_id().map_or(false, |selected| selected + 5 >= v.len())
+      }).unwrap_or(false);
+      if near_end {
+        load_game_page(s, &select_list);
+      }
+    })
+    .on_submit(move |s, game: &Game| {
+      cancel_query(s, "game_page");
+      s.pop_layer();
+      // the player's games are seen from their side.
+      let side = if game.black == submit_list.borrow().player_name { PieceColor::Black } else { PieceColor::White };
+      show_game(s, game, 0, side)
+    })
+    .with_name("game_list");
+  let layout = LinearLayout::vertical()
+    .child(controls)
+    .child(ScrollView::new(games).show_scrollbars(true).max_height(10));
+  Dialog::around(layout)
+    .button("Back", |s| {
+      cancel_query(s, "game_page");
+      s.pop_layer();
+    })
+    .with_name("game_selector")
+}
+
+fn reload_game_list(siv: &mut Cursive, game_list: &Rc<RefCell<GameList>>) {
+  {
+    let mut game_list = game_list.borrow_mut();
+    game_list.next_page = None;
+    game_list.exhausted = false;
+  }
+  siv.call_on_name("game_list", |v: &mut SelectView<Game>| v.clear());
+  load_game_page(siv, game_list);
+}
+
+fn load_game_page(siv: &mut Cursive, game_list: &Rc<RefCell<GameList>>) {
+  let (player_name, query, next_page) = {
+    let game_list = game_list.borrow();
+    if game_list.exhausted {
+      return;
+    }
+    (game_list.player_name.clone(), game_list.query.clone(), game_list.next_page.clone())
+  };
+  if next_page.is_none() {
+    siv.call_on_name("game_selector", |v: &mut Dialog| v.set_title("Loading games..."));
+  }
+  let game_list = game_list.clone();
+  run_query(siv, "game_page", move |pool| fetch_games_from_player(pool, player_name, query, next_page), move |s, page| {
+    match page {
+      Ok(page) => show_game_page(s, &game_list, page),
+      Err(err) => error_pop_up(s, err),
+    }
+  });
+}
+
+fn show_game_page(siv: &mut Cursive, game_list: &Rc<RefCell<GameList>>, (games, games_number): (Vec<(Game, GameCursor)>, Option<i64>)) {
+  let mut game_list = game_list.borrow_mut();
+  game_list.exhausted = (games.len() as i64) < GAMES_PAGE_SIZE;
+  if let Some((_, cursor)) = games.last() {
+    game_list.next_page = Some(cursor.clone());
+  }
+  let player_name = game_list.player_name.clone();
+  siv.call_on_name("game_list", |v: &mut SelectView<Game>| {
+    for (game, _) in games {
+      v.add_item(game_description(&player_name, &game), game);
+    }
+  });
+  if let Some(games_number) = games_number {
+    let title = match &game_list.query.opponent {
+      Some(opponent) => format!("{games_number} games played by {player_name} against {opponent}"),
+      None => format!("{games_number} games played by {player_name}"),
+    };
+    siv.call_on_name("game_selector", |v: &mut Dialog| v.set_title(title));
+  }
+  if std::mem::take(&mut game_list.focus_pending) {
+    siv.focus_name("game_list").ok();
+  }
+}
+
+fn game_description(player_name: &str, game: &Game) -> String {
+  let (color, opponent, opponent_elo) = if game.white == player_name {
+    ("W", &game.black, game.black_elo)
+  } else {
+    ("B", &game.white, game.white_elo)
+  };
+  let opponent_elo = opponent_elo.map_or("?".to_string(), |elo| elo.to_string());
+  let result = game.result.as_deref().unwrap_or("*");
+  let plies = game.plies.map_or("?".to_string(), |plies| plies.to_string());
+  format!("[{color}] vs {opponent} ({opponent_elo}) {result} in {plies} plies - {} @ {}", game.event, game.datetime)
 }
 
 fn error_pop_up<T: std::fmt::Debug>(siv: &mut Cursive, err: T) {
-  let debug = Dialog::around(TextView::new(format!("{:?}",err)));
+  let debug = Dialog::around(TextView::new(format!("{:?}",err))).dismiss_button("Ok");
   siv.add_layer(debug);
 }
 
-fn show_game(siv: &mut Cursive, game: &Game, db_url: String) {
-  let board_state = fetch_game(db_url, game.id.id).expect("Could not find game");
-  siv.add_layer(draw_board_state(&board_state));
+fn show_game(siv: &mut Cursive, game: &Game, move_idx: usize, side: PieceColor) {
+  load_game(siv, game.id.id, &format!("Loading {} vs {}", game.white, game.black), move_idx, side)
+}
 
-  // TODO: rewrite this mess
-  // possibly using View's internal `on_event`.
-  let board_1 = Rc::new(RefCell::new(board_state));
-  let board_2 = board_1.clone();
-  siv.add_global_callback(Key::Right, move |s| {
-    let mut board_state = board_1.borrow_mut();
-    if board_state.curr_move_idx < board_state.moves.len() {
-      board_state.curr_move_idx += 1;
-      s.pop_layer();
-      s.add_layer(draw_board_state(&board_state));
+fn load_game(siv: &mut Cursive, game_id: i32, label: &str, move_idx: usize, side: PieceColor) {
+  let session = siv.user_data::<Session>().unwrap();
+  let (related, theme, large_board) = (session.related.clone(), session.board_theme, session.large_board);
+  let novelty_min_games = session.novelty_min_games;
+  load(siv, "game", label,
+    move |pool| fetch_game(pool, related, novelty_min_games, game_id),
+    move |s, board_state| match board_state {
+      Ok(mut board_state) => {
+        board_state.curr_move_idx = move_idx.min(board_state.moves.len());
+        board_state.side = side;
+        board_state.theme = theme;
+        board_state.large_board = large_board;
+        let board_view = BoardView::new(board_state);
+        let name = board_view.name.clone();
+        s.add_layer(board_view.with_name(name));
+      },
+      Err(err) => error_pop_up(s, err),
+    });
+}
+
+/// Plies skipped by PageUp and PageDown.
+const PAGE_PLIES: usize = 10;
+
+static BOARD_VIEWS: AtomicUsize = AtomicUsize::new(0);
+
+/// A game being viewed, drawn again from its state after every change.
+///
+/// Each board has its own name, so the callbacks of its related games list and dialogs reach it
+/// even when several boards are open.
+pub struct BoardView {
+  state: BoardState,
+  name: String,
+  view: BoxedView,
+  click_areas: ClickAreas,
+  /// Layout fitting the size the board was last offered.
+  layout: BoardLayout,
+  /// Whether the related games list is focused before the next event, the view having been built
+  /// again for a new layout.
+  focus_pending: bool,
+}
+
+impl BoardView {
+  pub fn new(state: BoardState) -> Self {
+    let name = format!("board_view_{}", BOARD_VIEWS.fetch_add(1, Ordering::Relaxed));
+    let click_areas = ClickAreas::default();
+    let layout = BoardLayout::fit(Vec2::new(80, 24), &state);
+    let view = BoxedView::boxed(draw_board_state(&state, &name, &click_areas, &layout));
+    BoardView { state, name, view, click_areas, layout, focus_pending: false }
+  }
+
+  fn rebuild(&mut self) {
+    self.view = BoxedView::boxed(draw_board_state(&self.state, &self.name, &self.click_areas, &self.layout));
+  }
+
+  fn redraw(&mut self) {
+    self.rebuild();
+    self.focus_related_games().ok();
+  }
+
+  /// Focuses the related games list, which otherwise loses the first key to the layout focusing it.
+  fn focus_related_games(&mut self) -> Result<EventResult, ViewNotFound> {
+    self.view.focus_view(&Selector::Name("related_games"))
+  }
+
+  /// Shows the position after `ply` plies, or the final one if the game is shorter, leaving the analysis.
+  fn show_ply(&mut self, ply: usize) {
+    let ply = ply.min(self.state.moves.len());
+    if ply != self.state.curr_move_idx || self.state.analysis.is_some() {
+      self.state.analysis = None;
+      self.state.curr_move_idx = ply;
+      self.redraw();
     }
-  });
-  siv.add_global_callback(Key::Left, move |s| {
-    let mut board_state = board_2.borrow_mut();
-    if board_state.curr_move_idx > 0 {
-      board_state.curr_move_idx -= 1;
-      s.pop_layer();
-      s.add_layer(draw_board_state(&board_state))
+  }
+
+  /// Like `show_ply`, once the user agreed to lose the analysis if there is one.
+  pub fn jump_to(&mut self, ply: usize) -> EventResult {
+    if self.state.analysis.is_none() {
+      self.show_ply(ply);
+      return EventResult::Consumed(None);
+    }
+    let name = self.name.clone();
+    EventResult::with_cb(move |s| s.add_layer(leave_analysis_confirmation(name.clone(), ply)))
+  }
+
+  /// Plays a move from the position shown, starting an analysis unless it is the game's next move.
+  fn play_move(&mut self, mov: ChessMove) {
+    self.state.selected = None;
+    let curr_move_idx = self.state.curr_move_idx;
+    if self.state.analysis.is_none() && self.state.played.get(curr_move_idx) == Some(&mov) {
+      return self.show_ply(curr_move_idx + 1);
+    }
+    let root = self.state.position().clone();
+    self.state.analysis.get_or_insert_with(|| Analysis::new(curr_move_idx, root)).play(mov);
+    self.redraw();
+  }
+
+  fn play_text_move(&mut self, text: &str) -> Result<(), InsertionError> {
+    let mov = parse_move(self.state.position(), text)?;
+    self.play_move(mov);
+    Ok(())
+  }
+
+  /// Picks the origin of a move, or plays the move to `square` from the one already picked.
+  pub fn select_square(&mut self, square: Square) {
+    let from = self.state.selected.take();
+    let mov = from
+      .filter(|from| *from != square)
+      .and_then(|from| move_between(self.state.position(), from, square));
+    match mov {
+      Some(mov) => self.play_move(mov),
+      None => {
+        let position = self.state.position();
+        let own_piece = position.board().color_at(square) == Some(position.turn());
+        self.state.selected = (from != Some(square) && own_piece).then_some(square);
+        self.redraw();
+      },
+    }
+  }
+
+  fn flip(&mut self) {
+    self.state.side = !self.state.side;
+    self.redraw();
+  }
+
+  /// Switches between large and regular squares, for this board and the ones opened after it.
+  fn toggle_large_board(&mut self) -> EventResult {
+    self.state.large_board = !self.state.large_board;
+    self.redraw();
+    let large_board = self.state.large_board;
+    EventResult::with_cb(move |s| s.with_user_data(|session: &mut Session| session.large_board = large_board).unwrap_or_default())
+  }
+
+  /// Changes the theme of the board, and of the ones opened after it.
+  fn set_theme(&mut self, theme: BoardTheme) -> EventResult {
+    self.state.theme = theme;
+    self.redraw();
+    EventResult::with_cb(move |s| s.with_user_data(|session: &mut Session| session.board_theme = theme).unwrap_or_default())
+  }
+
+  /// The game as PGN, with the name of the file it is exported to by default.
+  fn pgn(&self) -> (String, String) {
+    let game = &self.state.game;
+    let file_name = format!("{}_vs_{}_{}.pgn", file_name_part(&game.white), file_name_part(&game.black), game.id.id);
+    (game_pgn(game, &self.state.tags, &self.state.moves), file_name)
+  }
+
+  fn toggle_cursor(&mut self) {
+    self.state.cursor = match self.state.cursor {
+      Some(_) => None,
+      None => Some(self.state.last_move_to_square().unwrap_or(Square::E2)),
+    };
+    self.state.selected = None;
+    self.redraw();
+  }
+
+  /// Moves the cursor by the given files and ranks, as seen on the screen.
+  fn move_cursor(&mut self, files: i32, ranks: i32) {
+    let Some(cursor) = self.state.cursor else {
+      return;
+    };
+    let (files, ranks) = match self.state.side {
+      PieceColor::White => (files, ranks),
+      PieceColor::Black => (-files, -ranks),
+    };
+    if let (Some(file), Some(rank)) = (cursor.file().offset(files), cursor.rank().offset(ranks)) {
+      self.state.cursor = Some(Square::from_coords(file, rank));
+      self.redraw();
+    }
+  }
+
+  fn leave_analysis(&mut self) {
+    if self.state.analysis.take().is_some() {
+      self.redraw();
+    }
+  }
+
+  /// Position of the analysis whose games are not looked up yet.
+  fn pending_lookup(&self) -> Option<Zobrist64> {
+    self.state.analysis.as_ref()
+      .and_then(Analysis::current_node)
+      .filter(|node| node.games.is_none())
+      .map(|node| node.board_hash)
+  }
+
+  fn lookup_pending(&self) -> EventResult {
+    match self.pending_lookup() {
+      Some(board_hash) => {
+        let name = self.name.clone();
+        EventResult::with_cb(move |s| lookup_analysis_position(s, &name, board_hash))
+      },
+      None => EventResult::Consumed(None),
+    }
+  }
+
+  fn set_analysis_games(&mut self, board_hash: Zobrist64, games: Vec<(Move, Game)>) {
+    if let Some(analysis) = &mut self.state.analysis {
+      analysis.set_games(board_hash, games);
+      self.redraw();
+    }
+  }
+
+  fn on_cursor_event(&mut self, event: &Event) -> Option<EventResult> {
+    let cursor = self.state.cursor?;
+    match event {
+      Event::Key(Key::Up) => self.move_cursor(0, 1),
+      Event::Key(Key::Down) => self.move_cursor(0, -1),
+      Event::Key(Key::Left) => self.move_cursor(-1, 0),
+      Event::Key(Key::Right) => self.move_cursor(1, 0),
+      Event::Key(Key::Enter) | Event::Char(' ') => {
+        self.select_square(cursor);
+        return Some(self.lookup_pending())
+      },
+      Event::Key(Key::Esc) if self.state.selected.is_some() => {
+        self.state.selected = None;
+        self.redraw();
+      },
+      Event::Key(Key::Esc) | Event::Char('c') => self.toggle_cursor(),
+      _ => return None,
     }
+    Some(EventResult::Consumed(None))
+  }
+
+  /// Innermost clickable part of the board drawn at `position`, on the screen.
+  fn clicked(&self, position: Vec2) -> Option<BoardClick> {
+    self.click_areas.borrow().iter().rev()
+      .find(|(area, _)| area.contains(position))
+      .map(|(_, click)| *click)
+  }
+
+  fn on_mouse_event(&mut self, position: Vec2, event: MouseEvent) -> Option<EventResult> {
+    let clicked = self.clicked(position)?;
+    match (event, clicked) {
+      (MouseEvent::Press(MouseButton::Left), BoardClick::Ply(ply)) => Some(self.jump_to(ply)),
+      (MouseEvent::Press(MouseButton::Left), BoardClick::Square(square)) => {
+        self.select_square(square);
+        Some(self.lookup_pending())
+      },
+      (MouseEvent::WheelUp | MouseEvent::WheelDown, _) => {
+        let key = if event == MouseEvent::WheelUp { Key::Left } else { Key::Right };
+        if let Some(result) = self.on_analysis_event(&Event::Key(key)) {
+          return Some(result);
+        }
+        let curr_move_idx = self.state.curr_move_idx;
+        Some(self.jump_to(if key == Key::Left { curr_move_idx.saturating_sub(1) } else { curr_move_idx + 1 }))
+      },
+      _ => None,
+    }
+  }
+
+  fn on_analysis_event(&mut self, event: &Event) -> Option<EventResult> {
+    let analysis = self.state.analysis.as_mut()?;
+    match event {
+      Event::Key(Key::Left) => {
+        if !analysis.undo() {
+          // at the root, going further back leaves the analysis.
+          let root_ply = analysis.root_ply;
+          return Some(self.jump_to(root_ply));
+        }
+        self.redraw();
+      },
+      Event::Key(Key::Right) => {
+        if analysis.redo() {
+          self.redraw();
+        }
+      },
+      Event::Key(Key::Esc) => self.leave_analysis(),
+      _ => return None,
+    }
+    Some(self.lookup_pending())
+  }
+
+  fn set_related_games(&mut self, related_games: Vec<Vec<RelatedGame>>) {
+    self.state.related_games = related_games;
+    self.redraw();
+  }
+
+  fn related_game(&self, index: usize) -> Option<&RelatedGame> {
+    self.state.related_games.get(self.state.curr_move_idx).and_then(|related| related.get(index))
+  }
+
+  fn show_related_path(&mut self, index: usize) {
+    let description = self.related_game(index)
+      .map(|related| related_path_description(&self.state, related))
+      .unwrap_or_default();
+    self.view.call_on_name("related_path", |v: &mut TextView| v.set_content(description));
+  }
+
+  /// Shows a related game at `move_idx`, the position it shares with the current one, keeping the current one in the trail.
+  fn follow_related_game(&mut self, mut board_state: BoardState, move_idx: usize) {
+    board_state.curr_move_idx = move_idx.min(board_state.moves.len());
+    board_state.side = self.state.side;
+    board_state.theme = self.state.theme;
+    board_state.large_board = self.state.large_board;
+    board_state.trail = std::mem::take(&mut self.state.trail);
+    let previous = std::mem::replace(&mut self.state, board_state);
+    self.state.trail.push(previous);
+    self.redraw();
+  }
+
+  fn follow_trail_back(&mut self) {
+    if let Some(mut previous) = self.state.trail.pop() {
+      previous.trail = std::mem::take(&mut self.state.trail);
+      previous.theme = self.state.theme;
+      previous.large_board = self.state.large_board;
+      self.state = previous;
+      self.redraw();
+    }
+  }
+
+  fn pawn_structure_games(&self) -> EventResult {
+    let pawn_hash = pawn_structure_hash(self.state.position());
+    let game_id = self.state.game.id.id;
+    EventResult::with_cb(move |s| {
+      load(s, "pawn_structure", "Looking up the pawn structure",
+        move |pool| fetch_games_from_pawn_structure(pool, pawn_hash, game_id),
+        |s, games| match games {
+          Ok(games) => {
+            let title = format!("{} other games reach this pawn structure", games.len());
+            s.add_layer(position_game_selector(title, games))
+          },
+          Err(err) => error_pop_up(s, err),
+        });
+    })
+  }
+}
+
+impl ViewWrapper for BoardView {
+  cursive::wrap_impl!(self.view: BoxedView);
+
+  fn wrap_draw(&self, printer: &Printer) {
+    self.click_areas.borrow_mut().clear();
+    self.view.draw(printer);
+  }
+
+  fn wrap_required_size(&mut self, constraint: Vec2) -> Vec2 {
+    let layout = BoardLayout::fit(constraint, &self.state);
+    if layout != self.layout {
+      self.layout = layout;
+      self.rebuild();
+      self.focus_pending = true;
+    }
+    self.view.required_size(constraint)
+  }
+
+  fn wrap_take_focus(&mut self, source: Direction) -> Result<EventResult, CannotFocus> {
+    self.focus_related_games().or_else(|_| self.view.take_focus(source))
+  }
+
+  fn wrap_on_event(&mut self, event: Event) -> EventResult {
+    if std::mem::take(&mut self.focus_pending) {
+      self.focus_related_games().ok();
+    }
+    if let Event::Mouse { position, event: mouse_event, .. } = event {
+      if let Some(result) = self.on_mouse_event(position, mouse_event) {
+        return result;
+      }
+    }
+    if let Some(result) = self.on_cursor_event(&event).or_else(|| self.on_analysis_event(&event)) {
+      return result;
+    }
+    let curr_move_idx = self.state.curr_move_idx;
+    match event {
+      Event::Key(Key::Right) => return self.jump_to(curr_move_idx + 1),
+      Event::Key(Key::Left) => return self.jump_to(curr_move_idx.saturating_sub(1)),
+      Event::Key(Key::PageDown) => return self.jump_to(curr_move_idx + PAGE_PLIES),
+      Event::Key(Key::PageUp) => return self.jump_to(curr_move_idx.saturating_sub(PAGE_PLIES)),
+      Event::Key(Key::Home) => return self.jump_to(0),
+      Event::Key(Key::End) => return self.jump_to(self.state.moves.len()),
+      Event::Char('b') => self.follow_trail_back(),
+      Event::Char('p') => return self.pawn_structure_games(),
+      Event::Char('c') => self.toggle_cursor(),
+      Event::Char('f') => self.flip(),
+      Event::Char('t') => {
+        let theme = self.state.theme;
+        return self.set_theme(BoardTheme { colors: theme.colors.next(), ..theme })
+      },
+      Event::Char('T') => {
+        let theme = self.state.theme;
+        return self.set_theme(BoardTheme { pieces: theme.pieces.next(), ..theme })
+      },
+      Event::Char('z') => return self.toggle_large_board(),
+      Event::Char('g') => {
+        let name = self.name.clone();
+        return EventResult::with_cb(move |s| s.add_layer(jump_to_ply_form(name.clone())))
+      },
+      Event::Char('m') => {
+        let name = self.name.clone();
+        return EventResult::with_cb(move |s| s.add_layer(move_form(name.clone())))
+      },
+      Event::Char(':') => {
+        let name = self.name.clone();
+        return EventResult::with_cb(move |s| s.add_layer(command_palette(Some(name.clone()))))
+      },
+      Event::Char('o') => {
+        let name = self.name.clone();
+        return EventResult::with_cb(move |s| {
+          let related = s.user_data::<Session>().unwrap().related.clone();
+          s.add_layer(related_games_form(related, name.clone()))
+        })
+      },
+      _ => return self.view.on_event(event),
+    }
+    EventResult::Consumed(None)
+  }
+}
+
+/// Columns taken by the movement list, its scrollbar included.
+const MOVEMENT_COLUMN_WIDTH: usize = 18;
+/// Columns added to the movement list by the time spent on each move.
+const MOVE_TIMES_WIDTH: usize = 12;
+/// Rows of the movement list when it is under the board.
+const MOVEMENT_ROWS_BELOW: usize = 4;
+/// Rows taken by the borders around the board and around its game description.
+const BOARD_BORDER_ROWS: usize = 4;
+/// Rows taken by the captured pieces and the material balance under the board.
+const MATERIAL_ROWS: usize = 3;
+/// Rows taken by the clocks and the time usage under the board, when the game has clocks.
+const CLOCK_ROWS: usize = 3;
+/// Narrowest and widest the related games list is drawn beside the board.
+const RELATED_COLUMN_WIDTH: (usize, usize) = (40, 72);
+
+/// Columns and rows of each square, regular or large.
+fn square_size(large: bool) -> Vec2 {
+  if large { Vec2::new(6, 2) } else { Vec2::new(3, 1) }
+}
+
+/// Columns and rows of the board, its rank and file labels included.
+fn chess_board_size(large: bool) -> Vec2 {
+  let square = square_size(large);
+  Vec2::new(3 + 8 * square.x + 1, 8 * square.y + 1)
+}
+
+/// How the panels of a board are arranged to fit the screen.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoardLayout {
+  pub large: bool,
+  /// The movement list under the board rather than beside it.
+  pub moves_below: bool,
+  pub moves_height: usize,
+  /// The related games beside the board rather than under it.
+  pub side_by_side: bool,
+  pub related_width: usize,
+}
+
+impl BoardLayout {
+  /// The layout fitting `size`, with large squares if asked for and there is room for them.
+  pub fn fit(size: Vec2, board_state: &BoardState) -> Self {
+    let (moves_width, below_board) = if board_state.clocks.is_empty() {
+      (MOVEMENT_COLUMN_WIDTH, MATERIAL_ROWS)
+    } else {
+      (MOVEMENT_COLUMN_WIDTH + MOVE_TIMES_WIDTH, MATERIAL_ROWS + CLOCK_ROWS)
+    };
+    let header_rows = BOARD_BORDER_ROWS + board_header_lines(board_state).len();
+    let fits = |board: Vec2| size.x >= board.x + 2 && size.y >= board.y + below_board + header_rows + MOVEMENT_ROWS_BELOW;
+    let large = board_state.large_board && fits(chess_board_size(true));
+    let board = chess_board_size(large);
+    let moves_below = size.x < board.x + moves_width + 2;
+    let (upper_width, moves_height) = if moves_below {
+      (board.x + 2, MOVEMENT_ROWS_BELOW)
+    } else {
+      (board.x + moves_width + 2, board.y + below_board)
+    };
+    let room_beside = size.x.saturating_sub(upper_width);
+    let side_by_side = room_beside >= RELATED_COLUMN_WIDTH.0;
+    let related_width = if side_by_side {
+      room_beside.min(RELATED_COLUMN_WIDTH.1)
+    } else {
+      upper_width.max(RELATED_COLUMN_WIDTH.0).min(size.x)
+    };
+    BoardLayout { large, moves_below, moves_height, side_by_side, related_width }
+  }
+}
+
+/// What clicking a part of the board does.
+#[derive(Debug, Clone, Copy)]
+pub enum BoardClick {
+  /// Scrolling over it steps through the game.
+  Board,
+  /// Jumps to the position after that ply.
+  Ply(usize),
+  /// Picks the square to move from or to.
+  Square(Square),
+}
+
+/// Where each clickable part of a board was drawn last, on the screen, innermost last.
+pub type ClickAreas = Rc<RefCell<Vec<(Rect, BoardClick)>>>;
+
+/// A view recording where it is drawn, so its board knows what a mouse event is over.
+struct ClickArea<V> {
+  view: V,
+  click: BoardClick,
+  areas: ClickAreas,
+}
+
+impl<V> ClickArea<V> {
+  fn new(view: V, click: BoardClick, areas: &ClickAreas) -> Self {
+    ClickArea { view, click, areas: areas.clone() }
+  }
+}
+
+impl<V: View> ViewWrapper for ClickArea<V> {
+  cursive::wrap_impl!(self.view: V);
+
+  fn wrap_draw(&self, printer: &Printer) {
+    // scrolled out of sight, partly or entirely.
+    let visible = printer.content_offset == Vec2::zero() && printer.output_size.x > 0 && printer.output_size.y > 0;
+    if visible {
+      self.areas.borrow_mut().push((Rect::from_size(printer.offset, printer.output_size), self.click));
+    }
+    self.view.draw(printer)
+  }
+}
+
+/// `name` with only letters, digits, `-` and `_` kept, so it cannot reach another directory.
+fn file_name_part(name: &str) -> String {
+  name.chars()
+    .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+    .collect()
+}
+
+/// Asks before leaving the analysis of the board named `board_name` to show the game at `ply`.
+fn leave_analysis_confirmation(board_name: String, ply: usize) -> impl View {
+  Dialog::text("Leave the analysis? The moves tried in it will be lost.")
+    .title("Analysis")
+    .button("Leave", move |s| {
+      s.pop_layer();
+      s.call_on_name(&board_name, |v: &mut BoardView| v.show_ply(ply));
+    })
+    .button("Stay", |s| { s.pop_layer(); })
+}
+
+fn jump_to_ply_form(board_name: String) -> impl View {
+  let submit_name = board_name.clone();
+  let ply = EditView::new()
+    .on_submit(move |s, ply| jump_to_ply(s, &submit_name, ply))
+    .with_name("jump_ply")
+    .fixed_width(6);
+  Dialog::around(ply)
+    .title("Jump to ply")
+    .button("Ok", move |s| {
+      let ply = s.call_on_name("jump_ply", |v: &mut EditView| v.get_content()).unwrap();
+      jump_to_ply(s, &board_name, &ply)
+    })
+    .button("Cancel", |s| { s.pop_layer(); })
+}
+
+fn jump_to_ply(siv: &mut Cursive, board_name: &str, ply: &str) {
+  match ply.trim().parse::<usize>() {
+    Ok(ply) => {
+      siv.pop_layer();
+      if let Some(result) = siv.call_on_name(board_name, |v: &mut BoardView| v.jump_to(ply)) {
+        result.process(siv);
+      }
+    },
+    Err(_) => error_pop_up(siv, "Ply must be a number"),
+  }
+}
+
+/// Command line opened with `:`, acting on the board named `board_name` if it was opened from one.
+fn command_palette(board_name: Option<String>) -> impl View {
+  let submit_name = board_name.clone();
+  let line = EditView::new()
+    .on_edit(update_command_completions)
+    .on_submit(move |s, line| run_command(s, submit_name.as_deref(), line))
+    .with_name("command_line");
+  let line = OnEventView::new(line).on_pre_event(Key::Tab, complete_command_line);
+  let mut choices = SelectView::<String>::new();
+  show_command_completions(&mut choices, &completions(""));
+  let choices = choices
+    .on_submit(|s, line: &String| {
+      if let Some(on_edit) = s.call_on_name("command_line", |v: &mut EditView| v.set_content(line.clone())) {
+        on_edit(s);
+      }
+      s.focus_name("command_line").ok();
+    })
+    .with_name("command_completions");
+  let layout = LinearLayout::vertical()
+    .child(LinearLayout::horizontal().child(TextView::new(":")).child(line.full_width()))
+    .child(ScrollView::new(choices).max_height(8))
+    .min_width(60);
+  let dialog = Dialog::around(layout)
+    .title("Command (Tab: complete)")
+    .button("Ok", move |s| {
+      let line = s.call_on_name("command_line", |v: &mut EditView| v.get_content()).unwrap();
+      run_command(s, board_name.as_deref(), &line)
+    })
+    .button("Cancel", |s| { s.pop_layer(); });
+  OnEventView::new(dialog).on_event(Key::Esc, |s| { s.pop_layer(); })
+}
+
+fn show_command_completions(choices: &mut SelectView<String>, completions: &[Completion]) {
+  choices.clear();
+  for completion in completions {
+    choices.add_item(format!("{}{}", completion.line, completion.hint), completion.line.clone());
+  }
+}
+
+fn update_command_completions(siv: &mut Cursive, line: &str, _cursor: usize) {
+  let found = completions(line);
+  siv.call_on_name("command_completions", |v: &mut SelectView<String>| show_command_completions(v, &found));
+  let search = match line.trim_start().split_once(' ') {
+    Some(("player", search)) if !search.trim().is_empty() => search.trim().to_string(),
+    _ => return cancel_query(siv, "command_players"),
+  };
+  run_query(siv, "command_players", move |pool| fetch_players_matching(pool, search.clone()), |s, players| {
+    s.call_on_name("command_completions", |v: &mut SelectView<String>| {
+      v.clear();
+      for (player_name, games) in players.unwrap_or_default() {
+        v.add_item(format!("player {player_name} ({games} games)"), format!("player {player_name}"));
+      }
+    });
+  });
+}
+
+/// Completes the command line as far as all its completions agree.
+fn complete_command_line(siv: &mut Cursive) {
+  let found: Vec<Completion> = siv.call_on_name("command_completions", |v: &mut SelectView<String>| {
+    v.iter().map(|(_, line)| Completion { line: line.clone(), hint: "" }).collect()
+  }).unwrap_or_default();
+  let Some(prefix) = common_prefix(&found) else {
+    return;
+  };
+  let on_edit = siv.call_on_name("command_line", |v: &mut EditView| {
+    (prefix.len() > v.get_content().len()).then(|| v.set_content(prefix))
   });
+  if let Some(Some(on_edit)) = on_edit {
+    on_edit(siv);
+  }
+}
+
+fn run_command(siv: &mut Cursive, board_name: Option<&str>, line: &str) {
+  let command = match Command::parse(line) {
+    Ok(command) => command,
+    Err(err) => return error_pop_up(siv, err),
+  };
+  cancel_query(siv, "command_players");
+  siv.pop_layer();
+  let on_board = |siv: &mut Cursive, action: &mut dyn FnMut(&mut BoardView) -> EventResult| {
+    match board_name.and_then(|name| siv.call_on_name(name, |v: &mut BoardView| action(v))) {
+      Some(result) => result.process(siv),
+      None => error_pop_up(siv, "No game is shown"),
+    }
+  };
+  match command {
+    Command::Player(player_name) => choose_player(siv, &player_name),
+    Command::Game(game_id) => load_game(siv, game_id, &format!("Loading game {game_id}"), 0, PieceColor::White),
+    Command::Fen(fen) => find_position(siv, &fen),
+    Command::Pattern(pattern) => find_pattern(siv, &pattern),
+    Command::Ply(ply) => on_board(siv, &mut |v| v.jump_to(ply)),
+    Command::Flip => on_board(siv, &mut |v| {
+      v.flip();
+      EventResult::Consumed(None)
+    }),
+    Command::ExportPgn(file_name) => {
+      let Some((pgn, default_name)) = board_name.and_then(|name| siv.call_on_name(name, |v: &mut BoardView| v.pgn())) else {
+        return error_pop_up(siv, "No game is shown");
+      };
+      let file_name = file_name.unwrap_or(default_name);
+      match std::fs::write(&file_name, pgn) {
+        Ok(()) => siv.add_layer(Dialog::info(format!("Game saved to {file_name}"))),
+        Err(err) => error_pop_up(siv, err),
+      }
+    },
+    Command::Theme(colors) => set_board_theme(siv, board_name, |theme| BoardTheme { colors, ..theme }),
+    Command::Pieces(pieces) => set_board_theme(siv, board_name, |theme| BoardTheme { pieces, ..theme }),
+    Command::Quit => siv.quit(),
+  }
+}
+
+/// Changes the theme of the boards opened next, and of the board named `board_name` if there is one.
+fn set_board_theme(siv: &mut Cursive, board_name: Option<&str>, change: impl Fn(BoardTheme) -> BoardTheme) {
+  let changed = board_name.and_then(|name| siv.call_on_name(name, |v: &mut BoardView| v.set_theme(change(v.state.theme))));
+  match changed {
+    Some(result) => result.process(siv),
+    None => {
+      siv.with_user_data(|session: &mut Session| session.board_theme = change(session.board_theme));
+    },
+  }
+}
+
+fn move_form(board_name: String) -> impl View {
+  let submit_name = board_name.clone();
+  let text = EditView::new()
+    .on_submit(move |s, text| play_typed_move(s, &submit_name, text))
+    .with_name("move_text")
+    .fixed_width(10);
+  Dialog::around(text)
+    .title("Play a move (SAN or UCI)")
+    .button("Ok", move |s| {
+      let text = s.call_on_name("move_text", |v: &mut EditView| v.get_content()).unwrap();
+      play_typed_move(s, &board_name, &text)
+    })
+    .button("Cancel", |s| { s.pop_layer(); })
 }
 
-pub fn draw_related_games_column(board_state: &BoardState) -> impl View {
+fn play_typed_move(siv: &mut Cursive, board_name: &str, text: &str) {
+  let played = siv.call_on_name(board_name, |v: &mut BoardView| v.play_text_move(text).map(|()| v.pending_lookup()));
+  match played {
+    Some(Ok(lookup)) => {
+      siv.pop_layer();
+      if let Some(board_hash) = lookup {
+        lookup_analysis_position(siv, board_name, board_hash);
+      }
+    },
+    Some(Err(err)) => error_pop_up(siv, err),
+    None => {},
+  }
+}
+
+/// Looks up the games reaching a position of the analysis, to list them under the board.
+fn lookup_analysis_position(siv: &mut Cursive, board_name: &str, board_hash: Zobrist64) {
+  let board_name = board_name.to_string();
+  run_query(siv, "analysis_position",
+    move |pool| fetch_games_from_position(pool, board_hash),
+    move |s, games| match games {
+      Ok(games) => {
+        s.call_on_name(&board_name, |v: &mut BoardView| v.set_analysis_games(board_hash, games));
+      },
+      Err(err) => error_pop_up(s, err),
+    });
+}
+
+fn related_games_form(related: RelatedGamesOptions, board_name: String) -> impl View {
+  let order = SelectView::new()
+    .popup()
+    .item("Elo", RelatedOrder::Elo)
+    .item("Date", RelatedOrder::Date)
+    .selected(if related.order == RelatedOrder::Elo { 0 } else { 1 })
+    .with_name("related_order");
+  let form = LinearLayout::vertical()
+    .child(LinearLayout::horizontal().child(TextView::new("Sort by:        ")).child(order))
+    .child(LinearLayout::horizontal()
+      .child(TextView::new("From ply:       "))
+      .child(EditView::new().content(related.min_ply.to_string()).with_name("related_min_ply").fixed_width(6)))
+    .child(LinearLayout::horizontal()
+      .child(TextView::new("Games per move: "))
+      .child(EditView::new().content(related.limit.to_string()).with_name("related_limit").fixed_width(6)));
+  Dialog::around(form)
+    .title("Related games")
+    .button("Ok", move |s| {
+      let order = s.call_on_name("related_order", |v: &mut SelectView<RelatedOrder>| v.selection())
+        .flatten()
+        .map_or(RelatedOrder::Elo, |order| *order);
+      let min_ply = s.call_on_name("related_min_ply", |v: &mut EditView| v.get_content().parse::<i32>().ok()).flatten();
+      let limit = s.call_on_name("related_limit", |v: &mut EditView| v.get_content().parse::<i64>().ok()).flatten();
+      let (Some(min_ply), Some(limit)) = (min_ply, limit) else {
+        return error_pop_up(s, "Ply and games per move must be numbers");
+      };
+      let related = RelatedGamesOptions { min_ply, limit, order };
+      s.with_user_data(|session: &mut Session| session.related = related.clone());
+      let Some(game_id) = s.call_on_name(&board_name, |v: &mut BoardView| v.state.game.id.id) else {
+        return;
+      };
+      let board_name = board_name.clone();
+      load(s, "related_games", "Looking up related games",
+        move |pool| fetch_related_games(pool, related, game_id),
+        move |s, related_games| match related_games {
+          Ok(related_games) => {
+            s.pop_layer();
+            s.call_on_name(&board_name, |v: &mut BoardView| v.set_related_games(related_games));
+          },
+          Err(err) => error_pop_up(s, err),
+        });
+    })
+    .button("Cancel", |s| { s.pop_layer(); })
+}
+
+/// Move order a related game took to the current position, if it differs from the game's.
+fn related_path_description(board_state: &BoardState, related: &RelatedGame) -> String {
+  let played_moves = &board_state.moves[..board_state.curr_move_idx.min(board_state.moves.len())];
+  if !related.transposed(played_moves) {
+    return String::new();
+  }
+  let path: Vec<String> = related.path.iter().enumerate()
+    .map(|(idx, san)| if idx % 2 == 0 { format!("{}. {}", idx / 2 + 1, san.0) } else { format!("{}", san.0) })
+    .collect();
+  format!("via {}", path.join(" "))
+}
+
+pub fn draw_related_games_column(board_state: &BoardState, board_name: &str) -> impl View {
   let empty = Vec::new();
   let related_board = board_state.related_games.get(board_state.curr_move_idx).unwrap_or(&empty);
-  let mut lines = LinearLayout::vertical();
-  for (played_move, game) in related_board.iter() {
-    let mvmt = TextView::new(if played_move.game_round % 2 == 1 {
-      format!("{} {}", (played_move.game_round + 1) / 2, played_move.san_plus.0)
-    } else {
-      format!("{} ... {}", played_move.game_round / 2, played_move.san_plus.0)
-    });
-    let game = TextView::new(format!("{} ({:?}) vs {} ({:?})", game.white, game.white_elo, game.black, game.black_elo));
-    let layout = LinearLayout::horizontal().child(mvmt).child(DummyView).child(game);
-    lines.add_child(layout);
+  let played_moves = &board_state.moves[..board_state.curr_move_idx.min(board_state.moves.len())];
+  let mut games = SelectView::<usize>::new();
+  for (index, related) in related_board.iter().enumerate() {
+    let game = &related.game;
+    let marker = if related.transposed(played_moves) { "⇄" } else { " " };
+    let white_elo = game.white_elo.map_or("?".to_string(), |elo| elo.to_string());
+    let black_elo = game.black_elo.map_or("?".to_string(), |elo| elo.to_string());
+    games.add_item(format!("{marker} {} {} ({white_elo}) vs {} ({black_elo})",
+      related.next_move.numbered_san(), game.white, game.black), index);
   }
-  let related_games = ScrollView::new(lines).show_scrollbars(true);
-  Dialog::around(related_games).title(format!("{} games reach this position", related_board.len())).full_height()
-    .max_width(44) // max width of board + movement column combined
-    // TODO: figure out a way to calculate this stuff before hand
+  let select_name = board_name.to_string();
+  let submit_name = board_name.to_string();
+  let games = games
+    .on_select(move |s, index| {
+      s.call_on_name(&select_name, |v: &mut BoardView| v.show_related_path(*index));
+    })
+    .on_submit(move |s, index| {
+      let selected = s.call_on_name(&submit_name, |v: &mut BoardView| {
+        v.related_game(*index).map(|related| (related.game.clone(), (related.next_move.game_round - 1) as usize))
+      }).flatten();
+      let Some((game, move_idx)) = selected else {
+        return;
+      };
+      let session = s.user_data::<Session>().unwrap();
+      let (related, novelty_min_games) = (session.related.clone(), session.novelty_min_games);
+      let board_name = submit_name.clone();
+      load(s, "game", &format!("Loading {} vs {}", game.white, game.black),
+        move |pool| fetch_game(pool, related, novelty_min_games, game.id.id),
+        move |s, board_state| match board_state {
+          Ok(board_state) => {
+            s.call_on_name(&board_name, |v: &mut BoardView| v.follow_related_game(board_state, move_idx));
+          },
+          Err(err) => error_pop_up(s, err),
+        });
+    })
+    .with_name("related_games");
+  let path = related_board.first()
+    .map(|related| related_path_description(board_state, related))
+    .unwrap_or_default();
+  let layout = LinearLayout::vertical()
+    .child(ScrollView::new(games).show_scrollbars(true).full_height())
+    .child(TextView::new(path).style(Effect::Italic).with_name("related_path"));
+  Dialog::around(layout).title(format!("{} games reach this position", related_board.len())).full_height()
 }
- 
-pub fn draw_movement_column(board_state: &BoardState) -> impl View {
+
+/// Games reaching a position of the analysis, opened on their own board.
+fn draw_analysis_games_column(node: &AnalysisNode, side: PieceColor) -> impl View {
+  let mut games = SelectView::new();
+  for (next_move, game) in node.games.iter().flatten() {
+    let white_elo = game.white_elo.map_or("?".to_string(), |elo| elo.to_string());
+    let black_elo = game.black_elo.map_or("?".to_string(), |elo| elo.to_string());
+    games.add_item(format!("  {} {} ({white_elo}) vs {} ({black_elo})",
+      next_move.numbered_san(), game.white, game.black), (next_move.clone(), game.clone()));
+  }
+  let games = games
+    .on_submit(move |s, (next_move, game): &(Move, Game)| show_game(s, game, (next_move.game_round - 1) as usize, side))
+    .with_name("related_games");
+  let title = match &node.games {
+    Some(games) => format!("{} games reach this position", games.len()),
+    None => "Looking up the position...".to_string(),
+  };
+  Dialog::around(ScrollView::new(games).show_scrollbars(true).full_height()).title(title).full_height()
+}
+
+pub fn draw_movement_column(board_state: &BoardState, click_areas: &ClickAreas, height: usize) -> impl View {
   let mut white_column = LinearLayout::vertical();
   let mut black_column = LinearLayout::vertical();
+  let mut white_times = LinearLayout::vertical();
+  let mut black_times = LinearLayout::vertical();
   let mut mvmt_count_col = LinearLayout::vertical();
   let seen     = Style { effects: Effect::Dim | Effect::Strikethrough, color: Color::Dark(BaseColor::Black).into() };
   let not_seen = Color::Dark(BaseColor::Black).into();
   let current = Style { effects: Effect::Blink | Effect::Bold, color: Color::Light(BaseColor::Magenta).into() };
+  let novelty = Style { effects: Effect::Underline.into(), color: Color::Light(BaseColor::Green).into() };
   for movement in board_state.moves.iter() {
     let order = (movement.game_round - 1).cmp(&(board_state.curr_move_idx as i32));
     let style = match order {
@@ -193,91 +1751,310 @@ pub fn draw_movement_column(board_state: &BoardState) -> impl View {
       std::cmp::Ordering::Equal => current,
       std::cmp::Ordering::Greater => not_seen,
     };
-    let mvmt_sans = TextView::new(format!("{}", movement.san_plus.0)).style(style);
+    let style = if board_state.novelty == Some(movement.game_round) {
+      Style::merge(&[style, novelty])
+    } else {
+      style
+    };
+    let san = board_state.theme.pieces.notation(&movement.san_plus.0.to_string());
+    let mvmt_sans = TextView::new(san).style(style);
+    let mvmt_sans = ClickArea::new(mvmt_sans, BoardClick::Ply(movement.game_round as usize), click_areas);
+    let spent = board_state.clocks.spent(movement.game_round as usize).map_or(String::new(), format_spent);
+    let spent = TextView::new(spent).style(Effect::Dim);
     if movement.game_round % 2 == 1 {
       white_column.add_child(mvmt_sans);
+      white_times.add_child(spent);
       mvmt_count_col.add_child(TextView::new(format!("{}", (movement.game_round + 1)/2)))
     } else {
       black_column.add_child(mvmt_sans);
+      black_times.add_child(spent);
     }
   }
-  let columns = LinearLayout::horizontal()
-    .child(mvmt_count_col)
-    .child(DummyView)
-    .child(white_column)
-    .child(DummyView)
-    .child(black_column);
-  ScrollView::new(columns).show_scrollbars(true).scroll_strategy(ScrollStrategy::KeepRow).max_height(9)
+  let columns = if board_state.clocks.is_empty() {
+    LinearLayout::horizontal()
+      .child(mvmt_count_col)
+      .child(DummyView)
+      .child(white_column)
+      .child(DummyView)
+      .child(black_column)
+  } else {
+    LinearLayout::horizontal()
+      .child(mvmt_count_col)
+      .child(DummyView)
+      .child(white_column)
+      .child(DummyView)
+      .child(white_times)
+      .child(DummyView)
+      .child(black_column)
+      .child(DummyView)
+      .child(black_times)
+  };
+  ScrollView::new(columns).show_scrollbars(true).scroll_strategy(ScrollStrategy::KeepRow).max_height(height)
 }
 
-pub fn draw_chess_board(board_state: &BoardState) -> impl View {
+pub fn draw_chess_board(board_state: &BoardState, click_areas: &ClickAreas, large: bool) -> impl View {
   let mut board_column = LinearLayout::vertical();
   let chess_board = board_state.current_board();
-  for row in (0..8).rev() {
+  // from the top left corner, as seen from the side at the bottom.
+  let (rows, cols): (Vec<u32>, Vec<u32>) = match board_state.side {
+    PieceColor::White => ((0..8).rev().collect(), (0..8).collect()),
+    PieceColor::Black => ((0..8).collect(), (0..8).rev().collect()),
+  };
+  for &row in &rows {
     let mut row_layout = LinearLayout::horizontal()
       .child(DummyView)
-      .child(TextView::new(format!("{}", row + 1)))
+      .child(TextView::new(format!("{}", row + 1)).fixed_height(square_size(large).y))
       .child(DummyView);
-    for col in 0..8 {
+    for &col in &cols {
       let square = Square::new(row * 8 + col);
       let piece = chess_board.piece_at(square);
-      let cell = square_view(board_state, piece, square);
-      row_layout.add_child(cell);
+      let cell = square_view(board_state, piece, square, large);
+      row_layout.add_child(ClickArea::new(cell, BoardClick::Square(square), click_areas));
     }
     row_layout.add_child(DummyView);
     board_column.add_child(row_layout);
   }
-  board_column.add_child(TextView::new("   A  B  C  D  E  F  G  H"));
+  let width = square_size(large).x;
+  let files: String = cols.iter().map(|col| format!("{:^width$}", (b'A' + *col as u8) as char)).collect();
+  board_column.add_child(TextView::new(format!("   {files}")));
   board_column
 }
 
-fn draw_board_state(board_state: &BoardState) -> impl View {
-  let game_description = LinearLayout::vertical()
-    .child(TextView::new(format!("{} [W] vs {} [B]", board_state.game.white, board_state.game.black)))
-    .child(TextView::new(format!("{} {}", board_state.game.event, board_state.game.datetime)));
-  let board = draw_chess_board(board_state);
-  let movement_column = draw_movement_column(board_state);
-  let middle = LinearLayout::horizontal().child(board).child(movement_column);
-  let related_games = draw_related_games_column(board_state);
-  let main_content = LinearLayout::vertical().child(Panel::new(game_description)).child(middle);
-  LinearLayout::vertical().child(Panel::new(main_content)).child(related_games)
+/// Pieces each side captured by the current position, who is ahead, and the balance over the whole game.
+fn draw_material(board_state: &BoardState, large: bool) -> impl View {
+  let board = board_state.current_board();
+  let balance = material_balance(board);
+  let mut column = LinearLayout::vertical();
+  for (color, name, advantage) in [(PieceColor::White, "White", balance), (PieceColor::Black, "Black", -balance)] {
+    let captured: String = captured_pieces(board, !color).into_iter()
+      .map(|role| board_state.theme.pieces.piece_char(role.of(!color)))
+      .collect();
+    let advantage = if advantage > 0 { format!(" +{advantage}") } else { String::new() };
+    column.add_child(TextView::new(format!(" {name}: {captured}{advantage}")));
+  }
+  let width = chess_board_size(large).x - " Balance: ".len();
+  let mut line = StyledString::plain(" Balance: ");
+  // white ahead above the middle, black ahead below it.
+  line.append(squeezed_sparkline(&board_state.balances, Some(board_state.curr_move_idx), width, centered_sparkline));
+  column.child(TextView::new(line))
 }
-  
-pub fn square_view(board_state: &BoardState, piece: Option<Piece>, square: Square) -> TextView {
-  let piece_char = match piece {
-    Some(Piece { role: Role::King, ..   }) => '\u{265A}',
-    Some(Piece { role: Role::Queen, ..  }) => '\u{265B}',
-    Some(Piece { role: Role::Rook, ..   }) => '\u{265C}',
-    Some(Piece { role: Role::Bishop, .. }) => '\u{265D}',
-    Some(Piece { role: Role::Knight, .. }) => '\u{265E}',
-    Some(Piece { role: Role::Pawn, ..   }) => '\u{265F}',
-    None => ' '
+
+/// A sparkline of `values` drawn by `draw` and squeezed into `width` columns by averaging them, the
+/// `current` one reversed.
+fn squeezed_sparkline(values: &[i32], current: Option<usize>, width: usize, draw: fn(&[i32]) -> String) -> StyledString {
+  let width = width.max(1);
+  let per_column = ((values.len() + width - 1) / width).max(1);
+  let values: Vec<i32> = values.chunks(per_column)
+    .map(|chunk| chunk.iter().sum::<i32>() / chunk.len() as i32)
+    .collect();
+  let current = current.map(|current| current / per_column);
+  let mut line = StyledString::new();
+  for (column, block) in draw(&values).chars().enumerate() {
+    if Some(column) == current {
+      line.append_styled(block.to_string(), Effect::Reverse);
+    } else {
+      line.append_plain(block.to_string());
+    }
+  }
+  line
+}
+
+/// Time left to each side at the current ply, and bars of the time they took for each move.
+fn draw_clocks(board_state: &BoardState, large: bool) -> impl View {
+  let mut column = LinearLayout::vertical();
+  if board_state.clocks.is_empty() {
+    return column;
+  }
+  let ply = board_state.curr_move_idx;
+  let (white, black) = board_state.clocks.remaining(ply);
+  let clock = |millis: Option<i32>| millis.map_or("?".to_string(), format_clock);
+  column.add_child(TextView::new(format!(" Clock: White {}  Black {}", clock(white), clock(black))));
+  let width = chess_board_size(large).x - " White: ".len();
+  for (first_ply, name) in [(1, "White"), (2, "Black")] {
+    let spent: Vec<i32> = (first_ply..=board_state.moves.len()).step_by(2)
+      .map(|ply| board_state.clocks.spent(ply).unwrap_or(0) / 1000)
+      .collect();
+    // Index of the last move that side played so far.
+    let current = ((ply + 2 - first_ply) / 2).checked_sub(1);
+    let mut line = StyledString::plain(format!(" {name}: "));
+    line.append(squeezed_sparkline(&spent, current, width, sparkline));
+    column.add_child(TextView::new(line));
+  }
+  column
+}
+
+/// Players, event, opening and result of a game, one line each, leaving out the tags it lacks.
+fn game_header(game: &Game, tags: &GameTags) -> Vec<String> {
+  let elo = |elo: Option<i32>| elo.map_or("?".to_string(), |elo| elo.to_string());
+  let mut lines = vec![format!("{} ({}) [W] vs {} ({}) [B]", game.white, elo(game.white_elo), game.black, elo(game.black_elo))];
+  let mut event = game.event.clone();
+  if let Some(site) = &tags.site {
+    event = format!("{event} @ {site}");
+  }
+  event = format!("{event}, {}", game.datetime);
+  if let Some(time_control) = &tags.time_control {
+    event = format!("{event}, {time_control}");
+  }
+  lines.push(event);
+  let opening: Vec<&str> = [&tags.eco, &tags.opening].into_iter().flatten().map(String::as_str).collect();
+  if !opening.is_empty() {
+    lines.push(opening.join(" "));
+  }
+  let result = game.result.as_deref().unwrap_or("*");
+  lines.push(match &tags.termination {
+    Some(termination) => format!("Result: {result} ({termination})"),
+    None => format!("Result: {result}"),
+  });
+  lines
+}
+
+/// How the game ended, at its last ply or when the position shown is over.
+fn final_state(board_state: &BoardState) -> Option<String> {
+  let position = board_state.position();
+  let color_name = |color: PieceColor| if color == PieceColor::White { "White" } else { "Black" };
+  if position.is_checkmate() {
+    return Some(format!("Checkmate, {} wins", color_name(!position.turn())));
+  }
+  if position.is_stalemate() {
+    return Some("Stalemate, draw".to_string());
+  }
+  if position.is_insufficient_material() {
+    return Some("Insufficient material, draw".to_string());
+  }
+  if board_state.analysis.is_some() || board_state.curr_move_idx < board_state.moves.len() {
+    return None;
+  }
+  let termination = board_state.tags.termination.as_deref().unwrap_or("").to_lowercase();
+  let loser = match board_state.game.result.as_deref() {
+    Some("1-0") => PieceColor::Black,
+    Some("0-1") => PieceColor::White,
+    Some("1/2-1/2") if termination.contains("time") => return Some("Draw, on time".to_string()),
+    Some("1/2-1/2") if position.halfmoves() >= 100 => return Some("Draw by the 50-move rule".to_string()),
+    Some("1/2-1/2") if termination.contains("repetition") => return Some("Draw by repetition".to_string()),
+    Some("1/2-1/2") if termination.contains("agree") => return Some("Draw agreed".to_string()),
+    // the result alone does not say whether it was agreed, repeated or adjudicated.
+    Some("1/2-1/2") => return Some("Draw".to_string()),
+    _ => return Some("Game unfinished".to_string()),
   };
-  let piece_color = if let Some(Piece {color: PieceColor::White, ..}) = piece {
-    Color::Dark(BaseColor::White)
-  } else if let Some(Piece {color: PieceColor::Black, ..}) = piece {
-    Color::Dark(BaseColor::Black)
+  Some(if termination.contains("time") {
+    format!("{} lost on time", color_name(loser))
+  } else if termination.contains("abandon") {
+    format!("{} abandoned the game", color_name(loser))
   } else {
-    Color::TerminalDefault
+    format!("{} resigned", color_name(loser))
+  })
+}
+
+/// The games followed to reach the current one, like `alice vs bob, ply 6 › carol vs alice`.
+fn trail_description(board_state: &BoardState) -> String {
+  let mut steps: Vec<String> = board_state.trail.iter()
+    .map(|previous| format!("{} vs {}, ply {}", previous.game.white, previous.game.black, previous.curr_move_idx))
+    .collect();
+  steps.push(format!("{} vs {}", board_state.game.white, board_state.game.black));
+  format!("{} (b: back)", steps.join(" › "))
+}
+
+/// Lines describing the game above the board, each with how it is drawn.
+fn board_header_lines(board_state: &BoardState) -> Vec<(String, Effect)> {
+  let mut lines = Vec::new();
+  if !board_state.trail.is_empty() {
+    lines.push((trail_description(board_state), Effect::Italic));
+  }
+  for line in game_header(&board_state.game, &board_state.tags) {
+    lines.push((line, Effect::Simple));
+  }
+  lines.push((match board_state.novelty.and_then(|round| board_state.moves.get(round as usize - 1)) {
+    Some(novelty) => format!("Novelty: {}", novelty.numbered_san()),
+    None => "No novelty".to_string(),
+  }, Effect::Simple));
+  if let Some(state) = final_state(board_state) {
+    lines.push((state, Effect::Bold));
+  }
+  if let Some(analysis) = &board_state.analysis {
+    let line = format!("Analysis from ply {}: {} (Esc: leave)", analysis.root_ply, analysis.line_description());
+    lines.push((line, Effect::Italic));
+    let tried: Vec<String> = analysis.children(analysis.current).iter()
+      .map(|node| format!("{}", analysis.nodes[*node].san_plus))
+      .collect();
+    if !tried.is_empty() {
+      lines.push((format!("Tried from here: {}", tried.join(", ")), Effect::Italic));
+    }
+  }
+  if board_state.cursor.is_some() {
+    lines.push(("Arrows: move, Enter: pick square, Esc: stop".to_string(), Effect::Italic));
+  }
+  lines
+}
+
+fn draw_board_state(board_state: &BoardState, board_name: &str, click_areas: &ClickAreas, layout: &BoardLayout) -> impl View {
+  let mut game_description = LinearLayout::vertical();
+  for (line, effect) in board_header_lines(board_state) {
+    game_description.add_child(TextView::new(line).style(effect));
+  }
+  let chess_board = LinearLayout::vertical()
+    .child(draw_chess_board(board_state, click_areas, layout.large))
+    .child(draw_material(board_state, layout.large))
+    .child(draw_clocks(board_state, layout.large));
+  let movement_column = draw_movement_column(board_state, click_areas, layout.moves_height);
+  let middle = if layout.moves_below { LinearLayout::vertical() } else { LinearLayout::horizontal() };
+  let middle = middle.child(chess_board).child(movement_column);
+  let related_games = match board_state.analysis.as_ref().and_then(Analysis::current_node) {
+    Some(node) => BoxedView::boxed(draw_analysis_games_column(node, board_state.side)),
+    None => BoxedView::boxed(draw_related_games_column(board_state, board_name)),
+  };
+  let main_content = LinearLayout::vertical().child(Panel::new(game_description)).child(middle);
+  let main_content = ClickArea::new(Panel::new(main_content), BoardClick::Board, click_areas);
+  let related_games = related_games.fixed_width(layout.related_width);
+  if layout.side_by_side {
+    // the board keeps its height, instead of stretching along the related games.
+    let main_content = LinearLayout::vertical().child(main_content).child(DummyView.full_height());
+    LinearLayout::horizontal().child(main_content).child(related_games)
+  } else {
+    LinearLayout::vertical().child(main_content).child(related_games)
+  }
+}
+  
+pub fn square_view(board_state: &BoardState, piece: Option<Piece>, square: Square, large: bool) -> TextView {
+  let colors = board_state.theme.colors.colors();
+  let piece_char = piece.map_or(' ', |piece| board_state.theme.pieces.piece_char(piece));
+  let piece_color = match piece {
+    Some(Piece { color: PieceColor::White, .. }) => colors.white_piece,
+    Some(Piece { color: PieceColor::Black, .. }) => colors.black_piece,
+    None => Color::TerminalDefault,
   };
   let (row, col) = {
     let index: u16 = square.into();
     (index / 8, index % 8)
   };
   let base_square_color = if row % 2 != col % 2 {
-    Color::Light(BaseColor::Magenta)
+    colors.light_square
   } else {
-    Color::Dark(BaseColor::Magenta)
+    colors.dark_square
   };
-  let to_color = Color::Light(BaseColor::Yellow);
-  let from_color = Color::Dark(BaseColor::Yellow);
   // not my proudest code, but i think this works.
   let square_color = match (board_state.last_move_from_square(), board_state.last_move_to_square()) {
-    (_,      Some(to)) if square == to   => to_color,
-    (Some(from),    _) if square == from => from_color,
+    _ if board_state.cursor == Some(square) => colors.cursor,
+    _ if board_state.selected == Some(square) => colors.selected,
+    (_,      Some(to)) if square == to   => colors.last_move_to,
+    (Some(from),    _) if square == from => colors.last_move_from,
     _ => base_square_color
   };
   let style =ColorStyle::new(piece_color, square_color);
-  TextView::new(format!(" {piece_char} ")).style(style)
+  let content = if large { format!("  {piece_char}   \n      ") } else { format!(" {piece_char} ") };
+  TextView::new(content).style(style)
 }
 
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn centered_sparklines_keep_zero_at_half_height() {
+    assert_eq!(centered_sparkline(&[]), "");
+    assert_eq!(centered_sparkline(&[0, 0, 0]), "▄▄▄");
+    assert_eq!(centered_sparkline(&[-9, 0, 9]), "▁▄█");
+    assert_eq!(centered_sparkline(&[0, 3]), "▄█");
+    assert_eq!(centered_sparkline(&[0, -2, -1]), "▄▁▂");
+  }
+}